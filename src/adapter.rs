@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::link::Link;
+
+// The adapter clocks every transfer, one byte at a time. At 8192 Hz a byte takes
+// 4096 T-cycles, then there's a pause before the next one.
+const BYTE_CYCLES: u64 = 4096;
+const PING_PERIOD: u64 = 2 * BYTE_CYCLES;
+// Each step of RATE's lower nibble slows the transmission phase down by this much.
+const RATE_STEP: u64 = 1024;
+// Answers to a ping that say a GameBoy is there.
+const ACK: u8 = 0x88;
+// Player 1 answers a whole ping with this to start the game, the adapter confirms
+// with a packet of 0xCC.
+const START: u8 = 0xAA;
+const STARTING: u8 = 0xCC;
+const PING: u8 = 0xFE;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+  // Packets of 0xFE, STAT, STAT, STAT. Each GameBoy answers 0x88, 0x88, RATE, SIZE.
+  Ping,
+  Start,
+  // Rounds of 4 * SIZE bytes: every GameBoy sends its SIZE bytes at the start
+  // of a round, and gets everybody's bytes from the previous round.
+  Transmission,
+}
+
+struct Adapter {
+  players: usize,
+  phase: Phase,
+  // Byte of the current packet (or round) being transferred, and the cycle it's due at.
+  index: usize,
+  due: u64,
+  // Which players got the current byte, and what they answered.
+  delivered: [bool; 4],
+  replies: [u8; 4],
+  // Everybody's answers to the current ping.
+  answers: [[u8; 4]; 4],
+  connected: [bool; 4],
+  // Set by player 1 during the ping phase.
+  rate: u8,
+  size: usize,
+  // Everybody's packet, being sent this round and being collected for the next one.
+  sending: Vec<u8>,
+  collecting: Vec<u8>,
+}
+
+impl Adapter {
+  fn byte(&self, player: usize) -> u8 {
+    match self.phase {
+      Phase::Ping if self.index == 0 => PING,
+      Phase::Ping => self.status(player),
+      Phase::Start => STARTING,
+      Phase::Transmission => self.sending[self.index],
+    }
+  }
+
+  // Which players are connected (bits 4-7) and which one this is (1-4).
+  fn status(&self, player: usize) -> u8 {
+    let connected = (0..4).filter(|&i| self.connected[i]).fold(0, |bits, i| bits | 0x10 << i);
+    connected | (player as u8 + 1)
+  }
+
+  fn packet_len(&self) -> usize {
+    match self.phase {
+      Phase::Ping | Phase::Start => 4,
+      Phase::Transmission => 4 * self.size,
+    }
+  }
+
+  fn period(&self) -> u64 {
+    match self.phase {
+      Phase::Ping | Phase::Start => PING_PERIOD,
+      Phase::Transmission => BYTE_CYCLES + (self.rate & 0x0F) as u64 * RATE_STEP,
+    }
+  }
+
+  // Every player got the current byte and answered it.
+  fn finish_transfer(&mut self) {
+    for player in 0..self.players {
+      let reply = self.replies[player];
+      match self.phase {
+        Phase::Transmission if self.index < self.size => self.collecting[player * self.size + self.index] = reply,
+        Phase::Transmission => {},
+        _ => self.answers[player][self.index] = reply,
+      }
+    }
+    self.delivered = [false; 4];
+    self.replies = [0xFF; 4];
+    self.due += self.period();
+    self.index += 1;
+    if self.index == self.packet_len() {
+      self.index = 0;
+      self.finish_packet();
+    }
+  }
+
+  fn finish_packet(&mut self) {
+    match self.phase {
+      Phase::Ping if self.answers[0] == [START; 4] => self.phase = Phase::Start,
+      Phase::Ping => {
+        for player in 0..self.players {
+          self.connected[player] = self.answers[player][..2] == [ACK, ACK];
+        }
+        self.rate = self.answers[0][2];
+        self.size = (self.answers[0][3] as usize).clamp(1, 16);
+      },
+      Phase::Start => {
+        self.phase = Phase::Transmission;
+        self.sending = vec![0; 4 * self.size];
+        self.collecting = vec![0; 4 * self.size];
+      },
+      // Player 1 sending nothing but 0xFF ends the game, back to pinging.
+      Phase::Transmission if self.collecting[..self.size].iter().all(|&b| b == 0xFF) => {
+        self.phase = Phase::Ping;
+        self.connected = [false; 4];
+      },
+      Phase::Transmission => {
+        self.sending = self.collecting.clone();
+        self.collecting.fill(0);
+      },
+    }
+  }
+}
+
+// One of the four ports of the DMG-07 Four Player Adapter. The adapter is the one
+// driving the clock: the GameBoys wait for transfers with the external clock, so
+// only the bytes they answer with reach it.
+pub struct AdapterPort {
+  adapter: Rc<RefCell<Adapter>>,
+  player: usize,
+}
+
+impl AdapterPort {
+  // An adapter with `players` (2-4) GameBoys plugged in, one port each. They have
+  // to be stepped in lockstep (see lockstep.rs), like a LocalLink.
+  pub fn ports(players: usize) -> Vec<Self> {
+    let adapter = Rc::new(RefCell::new(Adapter {
+      players,
+      phase: Phase::Ping,
+      index: 0,
+      due: PING_PERIOD,
+      delivered: [false; 4],
+      replies: [0xFF; 4],
+      answers: [[0xFF; 4]; 4],
+      connected: [false; 4],
+      rate: 0,
+      size: 1,
+      sending: Vec::new(),
+      collecting: Vec::new(),
+    }));
+    (0..players).map(|player| AdapterPort { adapter: adapter.clone(), player }).collect()
+  }
+}
+
+impl Link for AdapterPort {
+  // Nothing answers a GameBoy clocking a transfer itself.
+  fn send(&mut self, _cycle: u64, _data: u8) {}
+
+  fn receive(&mut self, _cycle: u64) -> u8 {
+    0xFF
+  }
+
+  fn incoming(&mut self, cycle: u64) -> Option<u8> {
+    let mut adapter = self.adapter.borrow_mut();
+    if cycle < adapter.due || adapter.delivered[self.player] {
+      return None;
+    }
+    adapter.delivered[self.player] = true;
+    Some(adapter.byte(self.player))
+  }
+
+  fn reply(&mut self, data: u8) {
+    let mut adapter = self.adapter.borrow_mut();
+    adapter.replies[self.player] = data;
+    if adapter.delivered[..adapter.players].iter().all(|&delivered| delivered) {
+      adapter.finish_transfer();
+    }
+  }
+
+  fn sync(&mut self, _cycle: u64) {}
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Every GameBoy gets the next byte and answers it, in order.
+  fn transfer(ports: &mut [AdapterPort], replies: &[u8]) -> Vec<u8> {
+    ports.iter_mut().zip(replies).map(|(port, &reply)| {
+      let byte = port.incoming(u64::MAX).unwrap();
+      port.reply(reply);
+      byte
+    }).collect()
+  }
+
+  // Same for a packet of 4 bytes, returns what each player got.
+  fn packet(ports: &mut [AdapterPort], replies: &[[u8; 4]]) -> Vec<[u8; 4]> {
+    let mut received = vec![[0; 4]; ports.len()];
+    for i in 0..4 {
+      let answers: Vec<u8> = replies.iter().map(|reply| reply[i]).collect();
+      for (player, byte) in transfer(ports, &answers).into_iter().enumerate() {
+        received[player][i] = byte;
+      }
+    }
+    received
+  }
+
+  #[test]
+  fn bytes_wait_for_everybody() {
+    let mut ports = AdapterPort::ports(2);
+    assert_eq!(ports[0].incoming(PING_PERIOD - 1), None);
+    assert_eq!(ports[0].incoming(PING_PERIOD), Some(PING));
+    ports[0].reply(ACK);
+    // Player 1 already has it, player 2 still has to answer.
+    assert_eq!(ports[0].incoming(u64::MAX), None);
+    assert_eq!(ports[1].incoming(PING_PERIOD), Some(PING));
+    ports[1].reply(ACK);
+    assert_eq!(ports[0].incoming(2 * PING_PERIOD - 1), None);
+    assert_eq!(ports[0].incoming(2 * PING_PERIOD), Some(0x01));
+  }
+
+  #[test]
+  fn ping_start_transmission_and_exit() {
+    let mut ports = AdapterPort::ports(2);
+    // Nobody is connected yet. Player 1 asks for 2 bytes per packet at the slowest rate.
+    let received = packet(&mut ports, &[[ACK, ACK, 0x0F, 2], [ACK, ACK, 0x00, 0x00]]);
+    assert_eq!(received, [[PING, 0x01, 0x01, 0x01], [PING, 0x02, 0x02, 0x02]]);
+    // Both answered, and player 1 starts the game.
+    let received = packet(&mut ports, &[[START; 4], [ACK, ACK, 0x00, 0x00]]);
+    assert_eq!(received, [[PING, 0x31, 0x31, 0x31], [PING, 0x32, 0x32, 0x32]]);
+    assert_eq!(packet(&mut ports, &[[0; 4], [0; 4]]), [[STARTING; 4], [STARTING; 4]]);
+
+    // Rounds of 4 * 2 bytes, everybody's packet comes back in the next one.
+    {
+      let adapter = ports[0].adapter.borrow();
+      assert!(adapter.phase == Phase::Transmission);
+      assert_eq!(adapter.period(), BYTE_CYCLES + 15 * RATE_STEP);
+    }
+    let round = |ports: &mut [AdapterPort], packets: [[u8; 2]; 2]| -> Vec<u8> {
+      (0..8).map(|i| {
+        let replies: Vec<u8> = packets.iter().map(|packet| packet.get(i).copied().unwrap_or(0)).collect();
+        let bytes = transfer(ports, &replies);
+        assert_eq!(bytes[0], bytes[1]);
+        bytes[0]
+      }).collect()
+    };
+    assert_eq!(round(&mut ports, [[0x11, 0x12], [0x21, 0x22]]), [0; 8]);
+    assert_eq!(round(&mut ports, [[0xFF, 0xFF], [0x23, 0x24]]), [0x11, 0x12, 0x21, 0x22, 0, 0, 0, 0]);
+
+    // Player 1 sent nothing but 0xFF: back to pinging, with nobody connected.
+    assert_eq!(packet(&mut ports, &[[ACK, ACK, 0, 1], [ACK, ACK, 0, 0]]), [[PING, 0x01, 0x01, 0x01], [PING, 0x02, 0x02, 0x02]]);
+    assert!(ports[0].adapter.borrow().phase == Phase::Ping);
+  }
+}
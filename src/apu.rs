@@ -0,0 +1,359 @@
+mod envelope;
+mod length;
+mod square;
+mod wave;
+mod noise;
+mod mixer;
+mod blip;
+
+use crate::model::Model;
+use square::Square;
+use wave::Wave;
+use noise::Noise;
+use mixer::{Mixer, HighPass, dac};
+use blip::Blip;
+
+pub struct Apu {
+  square1: Square,
+  square2: Square,
+  wave: Wave,
+  noise: Noise,
+  mixer: Mixer,
+  model: Model,
+  powered: bool,
+  // Frame sequencer step, 0-7. Clocked at 512 Hz by DIV.
+  step: u8,
+
+  // Mixer output, resampled to `sample_rate`. Nothing is produced until a sample
+  // rate is set.
+  blips: [Blip; 2],
+  sample_rate: u32,
+  // T-cycles since samples were last taken, and the mixer output at that point.
+  clock: u32,
+  last_output: (f32, f32),
+  // Each channel on its own, only when asked for.
+  stems: Option<Box<Stems>>,
+
+  // Debugging toggles, they only change what's mixed. When any channel is
+  // soloed, only soloed channels are heard.
+  muted: [bool; 4],
+  soloed: [bool; 4],
+  scope: Option<Scope>,
+  scope_clock: u32,
+}
+
+// What the oscilloscope overlay shows: each channel's recent output, and the sound registers.
+pub struct Scope {
+  // Output level (0-15) of square 1, square 2, wave and noise every SCOPE_PERIOD T-cycles.
+  pub waves: [Vec<u8>; 4],
+  // NR10-NR52, as read back by the CPU.
+  pub registers: [u8; 0x17],
+  pub audible: [bool; 4],
+}
+
+const SCOPE_PERIOD: u32 = 256;
+
+// Every channel's DAC output resampled separately, e.g. to record them to their own files.
+struct Stems {
+  blips: [Blip; 4],
+  filters: [HighPass; 4],
+  last_output: [f32; 4],
+  samples: [Vec<f32>; 4],
+}
+
+impl Apu {
+  pub fn new(model: Model) -> Self {
+    Apu {
+      square1: Square::new(true),
+      square2: Square::new(false),
+      wave: Wave::new(!model.is_cgb()),
+      noise: Noise::new(),
+      mixer: Mixer::new(),
+      model,
+      powered: true,
+      step: 0,
+      blips: [Blip::new(), Blip::new()],
+      sample_rate: 0,
+      clock: 0,
+      last_output: (0.0, 0.0),
+      stems: None,
+      muted: [false; 4],
+      soloed: [false; 4],
+      scope: None,
+      scope_clock: 0,
+    }
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match addr {
+      0xFF10..=0xFF14 => self.square1.read(addr - 0xFF10),
+      0xFF15..=0xFF19 => self.square2.read(addr - 0xFF15),
+      0xFF1A..=0xFF1E => self.wave.read(addr - 0xFF1A),
+      0xFF1F..=0xFF23 => self.noise.read(addr - 0xFF1F),
+      0xFF30..=0xFF3F => self.wave.read_ram((addr - 0xFF30) as usize),
+      0xFF26 => {
+        let status = (self.square1.enabled as u8)
+          | ((self.square2.enabled as u8) << 1)
+          | ((self.wave.enabled as u8) << 2)
+          | ((self.noise.enabled as u8) << 3);
+        ((self.powered as u8) << 7) | 0x70 | status
+      },
+      0xFF24 => self.mixer.nr50,
+      0xFF25 => self.mixer.nr51,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0xFF30..=0xFF3F => self.wave.write_ram((addr - 0xFF30) as usize, data),
+      0xFF26 => self.set_power(data & 0x80 != 0),
+      _ if self.powered => self.write_register(addr, data),
+      // Powered off, only the DMG's length counters can still be written.
+      0xFF11 | 0xFF16 | 0xFF20 if !self.model.is_cgb() => self.write_register(addr, data & 0x3F),
+      0xFF1B if !self.model.is_cgb() => self.write_register(addr, data),
+      _ => {},
+    }
+  }
+
+  fn write_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, data),
+      0xFF15..=0xFF19 => self.square2.write(addr - 0xFF15, data),
+      0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, data),
+      0xFF1F..=0xFF23 => self.noise.write(addr - 0xFF1F, data),
+      0xFF24 => self.mixer.nr50 = data,
+      0xFF25 => self.mixer.nr51 = data,
+      _ => {},
+    }
+  }
+
+  // Powering off clears every register up to NR51 (except the DMG's length counters,
+  // though NR11/NR21's duty still goes) and turns all channels off. Wave RAM is left alone.
+  fn set_power(&mut self, on: bool) {
+    if self.powered && !on {
+      for addr in 0xFF10..=0xFF25 {
+        let length = matches!(addr, 0xFF11 | 0xFF16 | 0xFF1B | 0xFF20);
+        if !length || self.model.is_cgb() {
+          self.write_register(addr, 0);
+        }
+      }
+      if !self.model.is_cgb() {
+        self.square1.clear_duty();
+        self.square2.clear_duty();
+      }
+      self.square1.enabled = false;
+      self.square2.enabled = false;
+      self.wave.enabled = false;
+      self.noise.enabled = false;
+    } else if !self.powered && on {
+      self.step = 0;
+      self.square1.power_on();
+      self.square2.power_on();
+    }
+    self.powered = on;
+  }
+
+  // Takes effect from the next take_samples on.
+  pub fn set_sample_rate(&mut self, rate: u32) {
+    self.sample_rate = rate;
+    self.mixer.set_sample_rate(rate, self.model.is_cgb());
+    for blip in &mut self.blips {
+      blip.set_rates(4194304.0, rate as f64);
+    }
+    if let Some(stems) = &mut self.stems {
+      for (blip, filter) in stems.blips.iter_mut().zip(&mut stems.filters) {
+        blip.set_rates(4194304.0, rate as f64);
+        filter.set_sample_rate(rate, self.model.is_cgb());
+      }
+    }
+  }
+
+  // Start resampling each channel on its own too, see take_stems.
+  pub fn enable_stems(&mut self) {
+    self.stems = Some(Box::new(Stems {
+      blips: [Blip::new(), Blip::new(), Blip::new(), Blip::new()],
+      filters: [HighPass::new(), HighPass::new(), HighPass::new(), HighPass::new()],
+      last_output: [0.0; 4],
+      samples: Default::default(),
+    }));
+    self.set_sample_rate(self.sample_rate);
+  }
+
+  // Mono samples of square 1, square 2, wave and noise, produced along with
+  // the last take_samples.
+  pub fn take_stems(&mut self) -> Option<[Vec<f32>; 4]> {
+    self.stems.as_mut().map(|stems| std::mem::take(&mut stems.samples))
+  }
+
+  pub fn toggle_mute(&mut self, channel: usize) {
+    self.muted[channel] = !self.muted[channel];
+  }
+
+  pub fn toggle_solo(&mut self, channel: usize) {
+    self.soloed[channel] = !self.soloed[channel];
+  }
+
+  fn audible(&self) -> [bool; 4] {
+    let solo = self.soloed.contains(&true);
+    let mut audible = [false; 4];
+    for (channel, audible) in audible.iter_mut().enumerate() {
+      *audible = if solo { self.soloed[channel] } else { !self.muted[channel] };
+    }
+    audible
+  }
+
+  pub fn scope_enabled(&self) -> bool {
+    self.scope.is_some()
+  }
+
+  // Start or stop capturing for take_scope.
+  pub fn enable_scope(&mut self, enabled: bool) {
+    self.scope = if enabled { Some(Scope { waves: Default::default(), registers: [0; 0x17], audible: [true; 4] }) } else { None };
+  }
+
+  // Everything captured since the last call, if the scope is enabled.
+  pub fn take_scope(&mut self) -> Option<Scope> {
+    let mut registers = [0; 0x17];
+    for (i, register) in registers.iter_mut().enumerate() {
+      *register = self.read(0xFF10 + i as u16);
+    }
+    let audible = self.audible();
+    self.scope.as_mut().map(|scope| Scope {
+      waves: std::mem::take(&mut scope.waves),
+      registers,
+      audible,
+    })
+  }
+
+  // Stereo samples (interleaved left, right) produced since the last call.
+  pub fn take_samples(&mut self) -> Vec<f32> {
+    if self.sample_rate == 0 {
+      return Vec::new();
+    }
+    for blip in &mut self.blips {
+      blip.end_frame(self.clock);
+    }
+    if let Some(stems) = &mut self.stems {
+      for channel in 0..4 {
+        stems.blips[channel].end_frame(self.clock);
+        let filter = &mut stems.filters[channel];
+        let samples = stems.blips[channel].read_samples().into_iter().map(|sample| filter.filter(sample));
+        stems.samples[channel].extend(samples);
+      }
+    }
+    self.clock = 0;
+    let left = self.blips[0].read_samples();
+    let right = self.blips[1].read_samples();
+    let mut samples = Vec::with_capacity(left.len() * 2);
+    for (left, right) in left.into_iter().zip(right) {
+      samples.push(self.mixer.high_pass(0, left));
+      samples.push(self.mixer.high_pass(1, right));
+    }
+    samples
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    if self.sample_rate == 0 {
+      self.run(cycles);
+      return;
+    }
+    // Stop wherever a channel's output may change, so the resampler gets every
+    // change at the T-cycle it happens.
+    let mut cycles = cycles;
+    while cycles > 0 {
+      let until_step = [self.square1.until_step(), self.square2.until_step(), self.wave.until_step(), self.noise.until_step()];
+      let step = until_step.iter().fold(cycles, |step, &until| step.min(until)).max(1);
+      self.run(step);
+      cycles -= step;
+    }
+  }
+
+  fn run(&mut self, cycles: u32) {
+    self.square1.tick(cycles);
+    self.square2.tick(cycles);
+    self.wave.tick(cycles);
+    self.noise.tick(cycles);
+
+    if let Some(scope) = &mut self.scope {
+      self.scope_clock += cycles;
+      while self.scope_clock >= SCOPE_PERIOD {
+        self.scope_clock -= SCOPE_PERIOD;
+        let outputs = [self.square1.output(), self.square2.output(), self.wave.output(), self.noise.output()];
+        for (wave, output) in scope.waves.iter_mut().zip(outputs) {
+          wave.push(output);
+        }
+      }
+    }
+
+    if self.sample_rate == 0 {
+      return;
+    }
+    // Only changes in the output go to the resampler.
+    self.clock += cycles;
+    let dacs = self.dacs();
+    let mut mixed = dacs;
+    for (dac, audible) in mixed.iter_mut().zip(self.audible()) {
+      if !audible {
+        *dac = None;
+      }
+    }
+    let (left, right) = self.mixer.mix(mixed);
+    if left != self.last_output.0 {
+      self.blips[0].add_delta(self.clock, left - self.last_output.0);
+    }
+    if right != self.last_output.1 {
+      self.blips[1].add_delta(self.clock, right - self.last_output.1);
+    }
+    self.last_output = (left, right);
+
+    if let Some(stems) = &mut self.stems {
+      for (channel, dac) in dacs.iter().enumerate() {
+        let output = dac.unwrap_or(0.0);
+        if output != stems.last_output[channel] {
+          stems.blips[channel].add_delta(self.clock, output - stems.last_output[channel]);
+          stems.last_output[channel] = output;
+        }
+      }
+    }
+  }
+
+  // Every channel's DAC output, None for the ones that are off.
+  fn dacs(&self) -> [Option<f32>; 4] {
+    [
+      Some(dac(self.square1.output())).filter(|_| self.square1.envelope.dac_enabled()),
+      Some(dac(self.square2.output())).filter(|_| self.square2.envelope.dac_enabled()),
+      Some(dac(self.wave.output())).filter(|_| self.wave.dac_enabled()),
+      Some(dac(self.noise.output())).filter(|_| self.noise.envelope.dac_enabled()),
+    ]
+  }
+
+  // Frame sequencer, clocked when DIV bit 4 falls (512 Hz):
+  // length at 256 Hz, sweep at 128 Hz and envelopes at 64 Hz.
+  pub fn frame_sequencer(&mut self) {
+    if !self.powered {
+      return;
+    }
+    match self.step {
+      0 | 4 => self.clock_length(),
+      2 | 6 => {
+        self.clock_length();
+        self.square1.clock_sweep();
+      },
+      7 => {
+        self.square1.clock_envelope();
+        self.square2.clock_envelope();
+        self.noise.clock_envelope();
+      },
+      _ => {},
+    }
+    self.step = (self.step + 1) % 8;
+  }
+
+  fn clock_length(&mut self) {
+    self.square1.clock_length();
+    self.square2.clock_length();
+    self.wave.clock_length();
+    self.noise.clock_length();
+  }
+}
@@ -0,0 +1,98 @@
+use std::f64::consts::PI;
+
+// Taps of the band-limited step, and how finely a step's position between two
+// output samples is resolved.
+const WIDTH: usize = 16;
+const PHASES: usize = 64;
+// Kernel cutoff as a fraction of the output Nyquist frequency, a little under 1
+// so the window's roll-off doesn't alias.
+const CUTOFF: f64 = 0.9;
+
+// Band-limited resampler, in the style of blip_buf. The APU's output is a series of
+// steps at the 4 MHz clock; instead of picking one value every N clocks (which
+// aliases everything above the output Nyquist back down), each step is added to
+// the output as a band-limited step: a windowed sinc impulse, integrated when read.
+// Output lags the input by WIDTH / 2 samples.
+pub struct Blip {
+  kernel: Vec<[f32; WIDTH]>,
+  // Deltas waiting to be integrated, buffer[0] is the next sample to be read.
+  buffer: Vec<f32>,
+  clocks_per_sample: f64,
+  // Where clock 0 of the current frame falls, in samples from buffer[0].
+  offset: f64,
+  // Running sum of the deltas read so far: the current output level.
+  level: f32,
+}
+
+impl Blip {
+  pub fn new() -> Self {
+    Blip {
+      kernel: kernel(),
+      buffer: Vec::new(),
+      clocks_per_sample: 1.0,
+      offset: 0.0,
+      level: 0.0,
+    }
+  }
+
+  pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+    self.clocks_per_sample = clock_rate / sample_rate;
+  }
+
+  // The input changed by `delta` at `clock` T-cycles into the frame.
+  pub fn add_delta(&mut self, clock: u32, delta: f32) {
+    let position = self.offset + clock as f64 / self.clocks_per_sample;
+    let index = position as usize;
+    let phase = ((position - index as f64) * PHASES as f64) as usize;
+    if self.buffer.len() < index + WIDTH {
+      self.buffer.resize(index + WIDTH, 0.0);
+    }
+    for (sample, tap) in self.buffer[index..index + WIDTH].iter_mut().zip(&self.kernel[phase]) {
+      *sample += delta * tap;
+    }
+  }
+
+  // The frame is `clocks` long: its samples can be read, and clock 0 of the next
+  // frame starts where this one ended.
+  pub fn end_frame(&mut self, clocks: u32) {
+    self.offset += clocks as f64 / self.clocks_per_sample;
+    let end = self.offset as usize;
+    if self.buffer.len() < end {
+      self.buffer.resize(end, 0.0);
+    }
+  }
+
+  // Every sample no later delta can touch anymore.
+  pub fn read_samples(&mut self) -> Vec<f32> {
+    let count = self.offset as usize;
+    self.offset -= count as f64;
+    let level = &mut self.level;
+    self.buffer.drain(..count).map(|delta| {
+      *level += delta;
+      *level
+    }).collect()
+  }
+}
+
+// One windowed sinc impulse per phase, each summing to 1 so a step of `delta`
+// ends up exactly `delta` higher once integrated.
+fn kernel() -> Vec<[f32; WIDTH]> {
+  let half = (WIDTH / 2) as f64;
+  (0..PHASES).map(|phase| {
+    let mut taps = [0.0; WIDTH];
+    for (i, tap) in taps.iter_mut().enumerate() {
+      let x = i as f64 - half - phase as f64 / PHASES as f64;
+      let sinc = if x == 0.0 { 1.0 } else { (PI * x * CUTOFF).sin() / (PI * x * CUTOFF) };
+      // Blackman window over -half-1..half+1.
+      let t = x / (half + 1.0);
+      let window = 0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos();
+      *tap = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    let mut row = [0.0; WIDTH];
+    for (out, tap) in row.iter_mut().zip(&taps) {
+      *out = (tap / sum) as f32;
+    }
+    row
+  }).collect()
+}
@@ -0,0 +1,51 @@
+// Volume envelope (NRx2). Every `period` 64 Hz ticks, volume goes one step up or down.
+pub struct Envelope {
+  initial: u8,
+  increase: bool,
+  period: u8,
+  timer: u8,
+  pub volume: u8,
+}
+
+impl Envelope {
+  pub fn new() -> Self {
+    Envelope { initial: 0, increase: false, period: 0, timer: 0, volume: 0 }
+  }
+
+  pub fn read(&self) -> u8 {
+    (self.initial << 4) | ((self.increase as u8) << 3) | self.period
+  }
+
+  pub fn write(&mut self, data: u8) {
+    self.initial = data >> 4;
+    self.increase = data & 0x08 != 0;
+    self.period = data & 0x07;
+  }
+
+  // The upper 5 bits of NRx2 double as the DAC power switch.
+  pub fn dac_enabled(&self) -> bool {
+    self.read() & 0xF8 != 0
+  }
+
+  pub fn trigger(&mut self) {
+    self.timer = self.period;
+    self.volume = self.initial;
+  }
+
+  pub fn clock(&mut self) {
+    if self.period == 0 {
+      return;
+    }
+    if self.timer > 0 {
+      self.timer -= 1;
+    }
+    if self.timer == 0 {
+      self.timer = self.period;
+      if self.increase && self.volume < 15 {
+        self.volume += 1;
+      } else if !self.increase && self.volume > 0 {
+        self.volume -= 1;
+      }
+    }
+  }
+}
@@ -0,0 +1,32 @@
+// Length counter. Counts down at 256 Hz and turns the channel off when it runs out.
+pub struct Length {
+  max: u16,
+  counter: u16,
+  pub enabled: bool,
+}
+
+impl Length {
+  pub fn new(max: u16) -> Self {
+    Length { max, counter: 0, enabled: false }
+  }
+
+  // NRx1 holds how many steps are already done, not how many are left.
+  pub fn load(&mut self, data: u16) {
+    self.counter = self.max - data;
+  }
+
+  pub fn trigger(&mut self) {
+    if self.counter == 0 {
+      self.counter = self.max;
+    }
+  }
+
+  // Returns false when the counter just expired and the channel should turn off.
+  pub fn clock(&mut self) -> bool {
+    if self.enabled && self.counter > 0 {
+      self.counter -= 1;
+      return self.counter != 0;
+    }
+    true
+  }
+}
@@ -0,0 +1,75 @@
+// Mixes the 4 channel DACs into stereo: NR51 routes each channel left and/or right,
+// NR50 sets the volume of each side. Resampled output goes through a high-pass filter,
+// like the capacitor on the real hardware's output, to take out the DC offset of the DACs.
+pub struct Mixer {
+  pub nr50: u8,
+  pub nr51: u8,
+  filters: [HighPass; 2],
+}
+
+impl Mixer {
+  pub fn new() -> Self {
+    Mixer { nr50: 0, nr51: 0, filters: [HighPass::new(), HighPass::new()] }
+  }
+
+  pub fn set_sample_rate(&mut self, rate: u32, cgb: bool) {
+    for filter in &mut self.filters {
+      filter.set_sample_rate(rate, cgb);
+    }
+  }
+
+  // Takes each channel's DAC output (None when the DAC is off), returns (left, right).
+  pub fn mix(&self, dacs: [Option<f32>; 4]) -> (f32, f32) {
+    let mut out = [0.0; 2];
+    for (channel, dac) in dacs.iter().enumerate() {
+      if let Some(level) = dac {
+        if self.nr51 & (1 << (channel + 4)) != 0 {
+          out[0] += level;
+        }
+        if self.nr51 & (1 << channel) != 0 {
+          out[1] += level;
+        }
+      }
+    }
+    let volumes = [(self.nr50 >> 4) & 0x07, self.nr50 & 0x07];
+    for side in 0..2 {
+      // Master volume goes from 1/8 to 8/8, 4 channels at full scale would be 4.0.
+      out[side] = out[side] / 4.0 * (volumes[side] + 1) as f32 / 8.0;
+    }
+    (out[0], out[1])
+  }
+
+  // Run one output sample of `side` (0 left, 1 right) through the capacitor.
+  pub fn high_pass(&mut self, side: usize, sample: f32) -> f32 {
+    self.filters[side].filter(sample)
+  }
+}
+
+pub struct HighPass {
+  capacitor: f32,
+  // How much the capacitor keeps per sample. Depends on the sample rate.
+  charge_factor: f32,
+}
+
+impl HighPass {
+  pub fn new() -> Self {
+    HighPass { capacitor: 0.0, charge_factor: 0.0 }
+  }
+
+  // The capacitor leaks a little every T-cycle, how much differs between models.
+  pub fn set_sample_rate(&mut self, rate: u32, cgb: bool) {
+    let per_cycle: f32 = if cgb { 0.998943 } else { 0.999958 };
+    self.charge_factor = per_cycle.powf(4194304.0 / rate as f32);
+  }
+
+  pub fn filter(&mut self, sample: f32) -> f32 {
+    let out = sample - self.capacitor;
+    self.capacitor = sample - out * self.charge_factor;
+    out
+  }
+}
+
+// A DAC turns the 0-15 digital level into -1.0..1.0.
+pub fn dac(level: u8) -> f32 {
+  level as f32 / 7.5 - 1.0
+}
@@ -0,0 +1,124 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Channel 4. Pseudo-random noise from a 15-bit LFSR (7-bit in width mode).
+pub struct Noise {
+  pub enabled: bool,
+  clock_shift: u8,
+  width_mode: bool,
+  divisor: u8,
+  // T-cycles until the LFSR shifts again.
+  timer: u32,
+  lfsr: u16,
+  length: Length,
+  pub envelope: Envelope,
+}
+
+impl Noise {
+  pub fn new() -> Self {
+    Noise {
+      enabled: false,
+      clock_shift: 0,
+      width_mode: false,
+      divisor: 0,
+      timer: 0,
+      lfsr: 0x7FFF,
+      length: Length::new(64),
+      envelope: Envelope::new(),
+    }
+  }
+
+  // Registers NR40-NR44, by offset (NR40 doesn't exist). Write-only bits read back as 1.
+  pub fn read(&self, reg: u16) -> u8 {
+    match reg {
+      2 => self.envelope.read(),
+      3 => (self.clock_shift << 4) | ((self.width_mode as u8) << 3) | self.divisor,
+      4 => ((self.length.enabled as u8) << 6) | 0xBF,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, reg: u16, data: u8) {
+    match reg {
+      1 => self.length.load((data & 0x3F) as u16),
+      2 => {
+        self.envelope.write(data);
+        if !self.envelope.dac_enabled() {
+          self.enabled = false;
+        }
+      },
+      3 => {
+        self.clock_shift = data >> 4;
+        self.width_mode = data & 0x08 != 0;
+        self.divisor = data & 0x07;
+      },
+      4 => {
+        self.length.enabled = data & 0x40 != 0;
+        if data & 0x80 != 0 {
+          self.trigger();
+        }
+      },
+      _ => {},
+    }
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.envelope.dac_enabled();
+    self.length.trigger();
+    self.timer = self.period();
+    self.envelope.trigger();
+    self.lfsr = 0x7FFF;
+  }
+
+  fn period(&self) -> u32 {
+    (DIVISORS[self.divisor as usize] as u32) << self.clock_shift
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    // Shifts of 14 and 15 stop the LFSR.
+    if !self.enabled || self.clock_shift >= 14 {
+      return;
+    }
+    let mut cycles = cycles;
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = self.period();
+      self.shift();
+    }
+    self.timer -= cycles;
+  }
+
+  // XOR the two low bits into bit 14 (and bit 6 in width mode), then shift right.
+  fn shift(&mut self) {
+    let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+    self.lfsr = (self.lfsr >> 1) | (xor << 14);
+    if self.width_mode {
+      self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+    }
+  }
+
+  // T-cycles until the LFSR shifts, when the output may change.
+  pub fn until_step(&self) -> u32 {
+    if !self.enabled || self.clock_shift >= 14 { u32::MAX } else { self.timer }
+  }
+
+  // Current output level, 0-15.
+  pub fn output(&self) -> u8 {
+    if !self.enabled || self.lfsr & 1 != 0 {
+      return 0;
+    }
+    self.envelope.volume
+  }
+
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+
+  pub fn clock_envelope(&mut self) {
+    self.envelope.clock();
+  }
+}
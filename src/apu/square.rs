@@ -0,0 +1,209 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+// Duty cycles, one bit per step of the waveform.
+const DUTY: [u8; 4] = [
+  0b0000_0001, // 12.5%
+  0b1000_0001, // 25%
+  0b1000_0111, // 50%
+  0b0111_1110, // 75%
+];
+
+// Square channels 1 (with sweep) and 2 (without).
+pub struct Square {
+  pub enabled: bool,
+  duty: u8,
+  duty_step: u8,
+  frequency: u16,
+  // T-cycles until the next duty step.
+  timer: u16,
+  length: Length,
+  pub envelope: Envelope,
+  sweep: Option<Sweep>,
+}
+
+// Channel 1 frequency sweep (NR10).
+struct Sweep {
+  period: u8,
+  negate: bool,
+  shift: u8,
+  timer: u8,
+  enabled: bool,
+  shadow: u16,
+  // A subtraction happened since the last trigger. Clearing negate after that
+  // turns the channel off.
+  negated: bool,
+}
+
+impl Square {
+  pub fn new(with_sweep: bool) -> Self {
+    let sweep = if with_sweep {
+      Some(Sweep { period: 0, negate: false, shift: 0, timer: 0, enabled: false, shadow: 0, negated: false })
+    } else {
+      None
+    };
+    Square {
+      enabled: false,
+      duty: 0,
+      duty_step: 0,
+      frequency: 0,
+      timer: 0,
+      length: Length::new(64),
+      envelope: Envelope::new(),
+      sweep,
+    }
+  }
+
+  // Registers NRx0-NRx4, by offset. Write-only bits read back as 1.
+  pub fn read(&self, reg: u16) -> u8 {
+    match reg {
+      0 => match &self.sweep {
+        Some(s) => 0x80 | (s.period << 4) | ((s.negate as u8) << 3) | s.shift,
+        None => 0xFF,
+      },
+      1 => (self.duty << 6) | 0x3F,
+      2 => self.envelope.read(),
+      3 => 0xFF,
+      4 => ((self.length.enabled as u8) << 6) | 0xBF,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, reg: u16, data: u8) {
+    match reg {
+      0 => {
+        if let Some(s) = &mut self.sweep {
+          s.period = (data >> 4) & 0x07;
+          let negate = data & 0x08 != 0;
+          if s.negate && !negate && s.negated {
+            self.enabled = false;
+          }
+          s.negate = negate;
+          s.shift = data & 0x07;
+        }
+      },
+      1 => {
+        self.duty = data >> 6;
+        self.length.load((data & 0x3F) as u16);
+      },
+      2 => {
+        self.envelope.write(data);
+        if !self.envelope.dac_enabled() {
+          self.enabled = false;
+        }
+      },
+      3 => self.frequency = (self.frequency & 0x700) | data as u16,
+      4 => {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+        self.length.enabled = data & 0x40 != 0;
+        if data & 0x80 != 0 {
+          self.trigger();
+        }
+      },
+      _ => {},
+    }
+  }
+
+  // The duty step restarts when the APU is powered back on.
+  pub fn power_on(&mut self) {
+    self.duty_step = 0;
+  }
+
+  // NRx1 without touching the length counter, for the DMG's power off.
+  pub fn clear_duty(&mut self) {
+    self.duty = 0;
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.envelope.dac_enabled();
+    self.length.trigger();
+    self.timer = (2048 - self.frequency) * 4;
+    self.envelope.trigger();
+    let frequency = self.frequency;
+    if let Some(s) = &mut self.sweep {
+      s.shadow = frequency;
+      s.timer = if s.period == 0 { 8 } else { s.period };
+      s.enabled = s.period != 0 || s.shift != 0;
+      s.negated = false;
+    }
+    // With a shift, the overflow check runs right away.
+    if self.sweep.as_ref().is_some_and(|s| s.shift != 0) && self.sweep_calculation() > 2047 {
+      self.enabled = false;
+    }
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    let mut cycles = cycles as u16;
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = (2048 - self.frequency) * 4;
+      self.duty_step = (self.duty_step + 1) % 8;
+    }
+    self.timer -= cycles;
+  }
+
+  // T-cycles until the duty step moves on, when the output may change.
+  pub fn until_step(&self) -> u32 {
+    self.timer as u32
+  }
+
+  // Current output level, 0-15.
+  pub fn output(&self) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+    let high = (DUTY[self.duty as usize] >> (7 - self.duty_step)) & 1 == 1;
+    if high { self.envelope.volume } else { 0 }
+  }
+
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+
+  pub fn clock_envelope(&mut self) {
+    self.envelope.clock();
+  }
+
+  pub fn clock_sweep(&mut self) {
+    let s = match &mut self.sweep {
+      Some(s) => s,
+      None => return,
+    };
+    if s.timer > 0 {
+      s.timer -= 1;
+    }
+    if s.timer != 0 {
+      return;
+    }
+    s.timer = if s.period == 0 { 8 } else { s.period };
+    if !s.enabled || s.period == 0 {
+      return;
+    }
+    let frequency = self.sweep_calculation();
+    let shift = self.sweep.as_ref().unwrap().shift;
+    if frequency > 2047 {
+      self.enabled = false;
+    } else if shift != 0 {
+      self.sweep.as_mut().unwrap().shadow = frequency;
+      self.frequency = frequency;
+      // The new frequency is checked for overflow again, but not written back.
+      if self.sweep_calculation() > 2047 {
+        self.enabled = false;
+      }
+    }
+  }
+
+  // Next sweep frequency: shadow +/- (shadow >> shift).
+  fn sweep_calculation(&mut self) -> u16 {
+    let s = self.sweep.as_mut().unwrap();
+    let delta = s.shadow >> s.shift;
+    if s.negate {
+      s.negated = true;
+      s.shadow - delta
+    } else {
+      s.shadow + delta
+    }
+  }
+}
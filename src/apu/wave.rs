@@ -0,0 +1,156 @@
+use super::length::Length;
+
+// Channel 3. Plays 32 4-bit samples from wave RAM (0xFF30-0xFF3F), high nibble first.
+pub struct Wave {
+  pub enabled: bool,
+  dac_enabled: bool,
+  // NR32 volume code: 0 = mute, 1 = 100%, 2 = 50%, 3 = 25%.
+  volume: u8,
+  frequency: u16,
+  timer: u16,
+  position: u8,
+  sample: u8,
+  length: Length,
+  ram: [u8; 16],
+  // T-cycles since the channel last read wave RAM.
+  since_read: u32,
+  // On DMG the CPU can only get to wave RAM while the channel plays if it hits the
+  // exact moment the channel reads it. CGB always sees the byte being played.
+  dmg: bool,
+}
+
+impl Wave {
+  pub fn new(dmg: bool) -> Self {
+    Wave {
+      enabled: false,
+      dac_enabled: false,
+      volume: 0,
+      frequency: 0,
+      timer: 0,
+      position: 0,
+      sample: 0,
+      length: Length::new(256),
+      ram: [0; 16],
+      since_read: u32::MAX,
+      dmg,
+    }
+  }
+
+  // Registers NR30-NR34, by offset. Write-only bits read back as 1.
+  pub fn read(&self, reg: u16) -> u8 {
+    match reg {
+      0 => ((self.dac_enabled as u8) << 7) | 0x7F,
+      1 => 0xFF,
+      2 => (self.volume << 5) | 0x9F,
+      3 => 0xFF,
+      4 => ((self.length.enabled as u8) << 6) | 0xBF,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, reg: u16, data: u8) {
+    match reg {
+      0 => {
+        self.dac_enabled = data & 0x80 != 0;
+        if !self.dac_enabled {
+          self.enabled = false;
+        }
+      },
+      1 => self.length.load(data as u16),
+      2 => self.volume = (data >> 5) & 0x03,
+      3 => self.frequency = (self.frequency & 0x700) | data as u16,
+      4 => {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+        self.length.enabled = data & 0x40 != 0;
+        if data & 0x80 != 0 {
+          self.trigger();
+        }
+      },
+      _ => {},
+    }
+  }
+
+  pub fn dac_enabled(&self) -> bool {
+    self.dac_enabled
+  }
+
+  pub fn read_ram(&self, index: usize) -> u8 {
+    match self.ram_index(index) {
+      Some(index) => self.ram[index],
+      None => 0xFF,
+    }
+  }
+
+  pub fn write_ram(&mut self, index: usize, data: u8) {
+    if let Some(index) = self.ram_index(index) {
+      self.ram[index] = data;
+    }
+  }
+
+  // While playing, accesses go to the byte the channel is on instead.
+  fn ram_index(&self, index: usize) -> Option<usize> {
+    if !self.enabled {
+      return Some(index);
+    }
+    if self.dmg && self.since_read > 1 {
+      return None;
+    }
+    Some(self.position as usize / 2)
+  }
+
+  fn trigger(&mut self) {
+    // DMG bug: retriggering right as the channel reads wave RAM corrupts its start.
+    if self.dmg && self.enabled && self.since_read <= 1 {
+      let next = ((self.position as usize + 1) % 32) / 2;
+      if next < 4 {
+        self.ram[0] = self.ram[next];
+      } else {
+        let block = next & !3;
+        for i in 0..4 {
+          self.ram[i] = self.ram[block + i];
+        }
+      }
+    }
+    self.enabled = self.dac_enabled;
+    self.length.trigger();
+    // There's a short delay before the first sample gets read.
+    self.timer = (2048 - self.frequency) * 2 + 6;
+    self.position = 0;
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    self.since_read = self.since_read.saturating_add(cycles);
+    if !self.enabled {
+      return;
+    }
+    let mut cycles = cycles as u16;
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = (2048 - self.frequency) * 2;
+      self.position = (self.position + 1) % 32;
+      let byte = self.ram[self.position as usize / 2];
+      self.sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+      self.since_read = cycles as u32;
+    }
+    self.timer -= cycles;
+  }
+
+  // T-cycles until the next sample is read, when the output may change.
+  pub fn until_step(&self) -> u32 {
+    if self.enabled { self.timer as u32 } else { u32::MAX }
+  }
+
+  // Current output level, 0-15.
+  pub fn output(&self) -> u8 {
+    if !self.enabled || self.volume == 0 {
+      return 0;
+    }
+    self.sample >> (self.volume - 1)
+  }
+
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+}
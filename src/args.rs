@@ -0,0 +1,176 @@
+use std::env;
+use gameboi::model::Model;
+use gameboi::ppu::Renderer;
+use gameboi::headless;
+use gameboi::pacer::Speed;
+
+// Command line: rustboy [--model <dmg0|dmg|mgb|sgb|sgb2|cgb|agb>] [--renderer <scanline|fifo>] [--scale N] [--speed <normal|fast|slow|uncapped>] [--keymap keys.cfg] [--sample-rate N] [--record-audio out.wav [--record-stems]] [--record-vgm out.vgm] [--song N] [--serial-out <out.txt|->] [--link-host PORT | --link-connect HOST:PORT | --printer out.png]
+//   [--link-local | --four-player <2-4>] [--link-rom other.gb]... [--keymap2 keys2.cfg] [--keymap3 keys3.cfg] [--keymap4 keys4.cfg]
+//   [--headless --frames N [--input script.txt] [--screenshot out.png [--screenshot-every N]]] /path/to/rom
+pub struct Args {
+  pub rom: String,
+  pub model: Option<Model>,
+  pub renderer: Renderer,
+  // Window size as a multiple of the 160x144 screen.
+  pub scale: u32,
+  pub speed: Speed,
+  // Keyboard bindings file, see Keymap.
+  pub keymap: Option<String>,
+  // Audio output rate, in samples per second.
+  pub sample_rate: u32,
+  // WAV file of everything played, and whether each channel also gets its own.
+  pub record_audio: Option<String>,
+  pub record_stems: bool,
+  // VGM log of the sound register writes.
+  pub record_vgm: Option<String>,
+  // GBS files: song to start with, 1-based.
+  pub song: Option<u8>,
+  // Bytes sent over the serial port are written here, - for stdout.
+  pub serial_out: Option<String>,
+  // Link cable over TCP: wait for the other GameBoy on this port, or connect to it.
+  pub link_host: Option<u16>,
+  pub link_connect: Option<String>,
+  // GameBoy Printer on the link port, printouts are saved next to this path.
+  pub printer: Option<String>,
+  // A second GameBoy in this process, linked to the first.
+  pub link_local: bool,
+  // Or 2-4 GameBoys plugged into a Four Player Adapter.
+  pub four_player: Option<usize>,
+  // Games of the other GameBoys, in order, if not the same one.
+  pub link_roms: Vec<String>,
+  // Keyboard bindings of players 2-4.
+  pub player_keymaps: [Option<String>; 3],
+  // Run without a window.
+  pub headless: Option<headless::Options>,
+}
+
+impl Args {
+  pub fn parse() -> Self {
+    let mut rom = None;
+    let mut model = None;
+    let mut renderer = Renderer::Scanline;
+    let mut scale = 3;
+    let mut speed = Speed::Normal;
+    let mut keymap = None;
+    let mut sample_rate = 48000;
+    let mut record_audio = None;
+    let mut record_stems = false;
+    let mut record_vgm = None;
+    let mut song = None;
+    let mut serial_out = None;
+    let mut link_host = None;
+    let mut link_connect = None;
+    let mut printer = None;
+    let mut link_local = false;
+    let mut four_player = None;
+    let mut link_roms = Vec::new();
+    let mut player_keymaps = [None, None, None];
+    let mut headless = false;
+    let mut frames = None;
+    let mut options = headless::Options {
+      frames: 0,
+      input: None,
+      screenshot: None,
+      screenshot_every: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--model" => {
+          let value = args.next().expect("Missing value for --model");
+          model = Some(value.parse().unwrap_or_else(|e: String| panic!("{}", e)));
+        },
+        "--renderer" => {
+          let value = args.next().expect("Missing value for --renderer");
+          renderer = value.parse().unwrap_or_else(|e: String| panic!("{}", e));
+        },
+        "--scale" => {
+          let value = args.next().expect("Missing value for --scale");
+          scale = value.parse().expect("Invalid value for --scale");
+          if scale < 1 {
+            panic!("--scale must be at least 1");
+          }
+        },
+        "--speed" => {
+          let value = args.next().expect("Missing value for --speed");
+          speed = value.parse().unwrap_or_else(|e: String| panic!("{}", e));
+        },
+        "--keymap" => keymap = Some(args.next().expect("Missing value for --keymap")),
+        "--sample-rate" => {
+          let value = args.next().expect("Missing value for --sample-rate");
+          sample_rate = value.parse().expect("Invalid value for --sample-rate");
+        },
+        "--record-audio" => record_audio = Some(args.next().expect("Missing value for --record-audio")),
+        "--record-stems" => record_stems = true,
+        "--record-vgm" => record_vgm = Some(args.next().expect("Missing value for --record-vgm")),
+        "--song" => {
+          let value = args.next().expect("Missing value for --song");
+          song = Some(value.parse().expect("Invalid value for --song"));
+        },
+        "--serial-out" => serial_out = Some(args.next().expect("Missing value for --serial-out")),
+        "--link-host" => {
+          let value = args.next().expect("Missing value for --link-host");
+          link_host = Some(value.parse().expect("Invalid value for --link-host"));
+        },
+        "--link-connect" => link_connect = Some(args.next().expect("Missing value for --link-connect")),
+        "--printer" => printer = Some(args.next().expect("Missing value for --printer")),
+        "--link-local" => link_local = true,
+        "--four-player" => {
+          let value = args.next().expect("Missing value for --four-player");
+          let players = value.parse().expect("Invalid value for --four-player");
+          if !(2..=4).contains(&players) {
+            panic!("--four-player takes 2 to 4 players");
+          }
+          four_player = Some(players);
+        },
+        "--link-rom" => link_roms.push(args.next().expect("Missing value for --link-rom")),
+        "--keymap2" | "--keymap3" | "--keymap4" => {
+          let player: usize = arg[8..].parse().unwrap();
+          player_keymaps[player - 2] = Some(args.next().unwrap_or_else(|| panic!("Missing value for {}", arg)));
+        },
+        "--headless" => headless = true,
+        "--frames" => {
+          let value = args.next().expect("Missing value for --frames");
+          frames = Some(value.parse().expect("Invalid value for --frames"));
+        },
+        "--input" => options.input = Some(args.next().expect("Missing value for --input")),
+        "--screenshot" => options.screenshot = Some(args.next().expect("Missing value for --screenshot")),
+        "--screenshot-every" => {
+          let value = args.next().expect("Missing value for --screenshot-every");
+          options.screenshot_every = Some(value.parse().expect("Invalid value for --screenshot-every"));
+        },
+        _ => rom = Some(arg),
+      }
+    }
+    // The local GameBoys already use the link port of the first one.
+    let local = link_local || four_player.is_some();
+    if local && (link_host.is_some() || link_connect.is_some() || printer.is_some()) {
+      panic!("--link-host, --link-connect and --printer can't be used with --link-local or --four-player");
+    }
+    if headless {
+      options.frames = frames.expect("Missing --frames for --headless");
+    }
+    Args {
+      rom: rom.expect("No ROM file provided"),
+      model,
+      renderer,
+      scale,
+      speed,
+      keymap,
+      sample_rate,
+      record_audio,
+      record_stems,
+      record_vgm,
+      song,
+      serial_out,
+      link_host,
+      link_connect,
+      printer,
+      link_local,
+      four_player,
+      link_roms,
+      player_keymaps,
+      headless: if headless { Some(options) } else { None },
+    }
+  }
+}
@@ -0,0 +1,46 @@
+#[cfg(feature = "sdl2")]
+mod ring;
+#[cfg(feature = "sdl2")]
+mod sdl;
+mod wav;
+
+pub use wav::Recorder;
+
+// Somewhere for the APU's samples to go.
+pub trait Audio {
+  // Interleaved stereo samples (left, right) at `rate`.
+  fn queue(&mut self, samples: &[f32]);
+  // Rate the APU produces samples at. It never changes, so recordings made from
+  // the same samples play at the right pitch.
+  fn rate(&self) -> u32;
+}
+
+// Throws everything away, for headless runs or when there's no sound device.
+pub struct NullAudio {
+  rate: u32,
+}
+
+impl NullAudio {
+  pub fn new(rate: u32) -> Self {
+    NullAudio { rate }
+  }
+}
+
+impl Audio for NullAudio {
+  fn queue(&mut self, _samples: &[f32]) {}
+
+  fn rate(&self) -> u32 {
+    self.rate
+  }
+}
+
+// The best backend available at `rate` samples per second: SDL2 when built with
+// the `sdl2` feature, otherwise silence.
+pub fn open(rate: u32) -> Box<dyn Audio> {
+  #[cfg(feature = "sdl2")]
+  match sdl::SdlAudio::open(rate) {
+    Ok(audio) => return Box::new(audio),
+    Err(e) => eprintln!("No audio: {}", e),
+  }
+  Box::new(NullAudio::new(rate))
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+// Single producer, single consumer ring buffer of samples. The emulation thread
+// pushes, the audio callback pops, neither ever waits on the other. Samples are
+// stored as their bits so plain atomics can hold them.
+pub struct Ring {
+  buffer: Box<[AtomicU32]>,
+  // Total samples ever read and written, wrapping. Only the consumer moves `read`
+  // and only the producer moves `write`.
+  read: AtomicUsize,
+  write: AtomicUsize,
+}
+
+impl Ring {
+  pub fn new(capacity: usize) -> Self {
+    Ring {
+      buffer: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+      read: AtomicUsize::new(0),
+      write: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.buffer.len()
+  }
+
+  // Samples waiting to be popped.
+  pub fn len(&self) -> usize {
+    self.write.load(Ordering::Acquire).wrapping_sub(self.read.load(Ordering::Acquire))
+  }
+
+  // Push as many samples as fit. Returns how many did.
+  pub fn push(&self, samples: &[f32]) -> usize {
+    let write = self.write.load(Ordering::Relaxed);
+    let free = self.capacity() - write.wrapping_sub(self.read.load(Ordering::Acquire));
+    let count = samples.len().min(free);
+    for (i, sample) in samples[..count].iter().enumerate() {
+      self.buffer[write.wrapping_add(i) % self.capacity()].store(sample.to_bits(), Ordering::Relaxed);
+    }
+    self.write.store(write.wrapping_add(count), Ordering::Release);
+    count
+  }
+
+  // Fill `out` with as many samples as there are. Returns how many it got.
+  pub fn pop(&self, out: &mut [f32]) -> usize {
+    let read = self.read.load(Ordering::Relaxed);
+    let count = out.len().min(self.write.load(Ordering::Acquire).wrapping_sub(read));
+    for (i, sample) in out[..count].iter_mut().enumerate() {
+      *sample = f32::from_bits(self.buffer[read.wrapping_add(i) % self.capacity()].load(Ordering::Relaxed));
+    }
+    self.read.store(read.wrapping_add(count), Ordering::Release);
+    count
+  }
+}
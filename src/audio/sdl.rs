@@ -0,0 +1,108 @@
+extern crate sdl2;
+use std::sync::Arc;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use super::Audio;
+use super::ring::Ring;
+
+// Samples the device asks for at once, per channel.
+const DEVICE_SAMPLES: u16 = 1024;
+// Ring buffer size, in stereo samples. The emulator tries to keep it half full.
+const BUFFER_SAMPLES: usize = 8192;
+// Most the output is stretched by to keep the buffer level. Small enough not to be heard.
+const MAX_DELTA: f64 = 0.005;
+
+struct Callback {
+  ring: Arc<Ring>,
+  // Last sample played, repeated on underrun instead of clicking to silence.
+  last: [f32; 2],
+}
+
+impl AudioCallback for Callback {
+  type Channel = f32;
+
+  fn callback(&mut self, out: &mut [f32]) {
+    let count = self.ring.pop(out);
+    if count >= 2 {
+      self.last.copy_from_slice(&out[count - 2..count]);
+    }
+    for (i, sample) in out.iter_mut().enumerate().skip(count) {
+      *sample = self.last[i % 2];
+    }
+  }
+}
+
+pub struct SdlAudio {
+  // The device stops playing when dropped, and SDL shuts down with the context.
+  _context: sdl2::Sdl,
+  _device: AudioDevice<Callback>,
+  ring: Arc<Ring>,
+  // What the APU produces, and what the device plays.
+  rate: u32,
+  device_rate: u32,
+  // Where the next output sample falls between `previous` and the next input sample, 0-1.
+  position: f64,
+  previous: [f32; 2],
+}
+
+impl SdlAudio {
+  pub fn open(rate: u32) -> Result<Self, String> {
+    let context = sdl2::init()?;
+    let audio = context.audio()?;
+    let desired = AudioSpecDesired {
+      freq: Some(rate as i32),
+      channels: Some(2),
+      samples: Some(DEVICE_SAMPLES),
+    };
+    let ring = Arc::new(Ring::new(BUFFER_SAMPLES * 2));
+    // The device may not take the rate we asked for, the APU's samples are stretched to whatever it does.
+    let mut device_rate = rate;
+    let device = audio.open_playback(None, &desired, |spec| {
+      device_rate = spec.freq as u32;
+      Callback { ring: ring.clone(), last: [0.0; 2] }
+    })?;
+    device.resume();
+    Ok(SdlAudio {
+      _context: context,
+      _device: device,
+      ring,
+      rate,
+      device_rate,
+      position: 0.0,
+      previous: [0.0; 2],
+    })
+  }
+
+  // Dynamic rate control: the host's sound card and the emulator's pacing never
+  // run at exactly the same speed, so play slightly more samples when the buffer
+  // runs low and slightly fewer when it fills up.
+  fn output_rate(&self) -> f64 {
+    let fill = self.ring.len() as f64 / self.ring.capacity() as f64;
+    let ratio = 1.0 + MAX_DELTA * (1.0 - 2.0 * fill);
+    self.device_rate as f64 * ratio
+  }
+}
+
+impl Audio for SdlAudio {
+  // Resampled to the output rate with linear interpolation: the APU's output is
+  // already band-limited, and the rates are close. Samples that don't fit are
+  // dropped, e.g. when fast-forwarding.
+  fn queue(&mut self, samples: &[f32]) {
+    let step = self.rate as f64 / self.output_rate();
+    let mut out = Vec::with_capacity(samples.len() + 16);
+    for frame in samples.chunks_exact(2) {
+      while self.position < 1.0 {
+        let t = self.position as f32;
+        out.push(self.previous[0] + (frame[0] - self.previous[0]) * t);
+        out.push(self.previous[1] + (frame[1] - self.previous[1]) * t);
+        self.position += step;
+      }
+      self.position -= 1.0;
+      self.previous = [frame[0], frame[1]];
+    }
+    self.ring.push(&out);
+  }
+
+  fn rate(&self) -> u32 {
+    self.rate
+  }
+}
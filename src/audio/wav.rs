@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use crate::path::suffixed_path;
+
+// 16-bit PCM WAV file. The sizes in the header are filled in when it's dropped.
+pub struct WavWriter {
+  file: BufWriter<File>,
+  channels: u16,
+  // Bytes of sample data written so far.
+  data_size: u32,
+}
+
+impl WavWriter {
+  pub fn create(path: &str, channels: u16, rate: u32) -> Self {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    let block_align = channels * 2;
+    file.write_all(b"RIFF").unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    file.write_all(b"WAVEfmt ").unwrap();
+    file.write_all(&16u32.to_le_bytes()).unwrap();
+    // PCM
+    file.write_all(&1u16.to_le_bytes()).unwrap();
+    file.write_all(&channels.to_le_bytes()).unwrap();
+    file.write_all(&rate.to_le_bytes()).unwrap();
+    file.write_all(&(rate * block_align as u32).to_le_bytes()).unwrap();
+    file.write_all(&block_align.to_le_bytes()).unwrap();
+    file.write_all(&16u16.to_le_bytes()).unwrap();
+    file.write_all(b"data").unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    WavWriter { file, channels, data_size: 0 }
+  }
+
+  // Samples are -1.0..1.0, interleaved when there's more than one channel.
+  pub fn write(&mut self, samples: &[f32]) {
+    for sample in samples {
+      let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+      self.file.write_all(&value.to_le_bytes()).unwrap();
+    }
+    self.data_size += samples.len() as u32 * 2;
+  }
+}
+
+impl Drop for WavWriter {
+  fn drop(&mut self) {
+    // A stereo file can't end halfway through a sample.
+    let data_size = self.data_size - self.data_size % (self.channels as u32 * 2);
+    self.file.seek(SeekFrom::Start(4)).unwrap();
+    self.file.write_all(&(36 + data_size).to_le_bytes()).unwrap();
+    self.file.seek(SeekFrom::Start(40)).unwrap();
+    self.file.write_all(&data_size.to_le_bytes()).unwrap();
+    self.file.flush().unwrap();
+  }
+}
+
+// Records what the APU plays: the stereo mix, and optionally each channel to its own file.
+pub struct Recorder {
+  mix: WavWriter,
+  stems: Option<[WavWriter; 4]>,
+}
+
+impl Recorder {
+  // Stems go next to `path`: out.wav -> out-square1.wav, out-square2.wav, out-wave.wav, out-noise.wav
+  pub fn create(path: &str, stems: bool, rate: u32) -> Self {
+    let stems = if stems {
+      let stem = |name| WavWriter::create(&suffixed_path(path, name), 1, rate);
+      Some([stem("square1"), stem("square2"), stem("wave"), stem("noise")])
+    } else {
+      None
+    };
+    Recorder { mix: WavWriter::create(path, 2, rate), stems }
+  }
+
+  pub fn has_stems(&self) -> bool {
+    self.stems.is_some()
+  }
+
+  pub fn write(&mut self, samples: &[f32], stem_samples: Option<[Vec<f32>; 4]>) {
+    self.mix.write(samples);
+    if let (Some(stems), Some(stem_samples)) = (&mut self.stems, stem_samples) {
+      for (stem, samples) in stems.iter_mut().zip(&stem_samples) {
+        stem.write(samples);
+      }
+    }
+  }
+}
@@ -1,19 +1,171 @@
+use crate::model::Model;
+use crate::ppu::Ppu;
+use crate::joypad::Joypad;
+use crate::timer::Timer;
+use crate::apu::Apu;
+use crate::vgm::VgmLog;
+use crate::serial::Serial;
+
+// Interrupt sources, as laid out in IF (0xFF0F) and IE (0xFFFF). Lower bits have priority.
+pub enum Interrupt {
+  VBlank = 1 << 0,
+  Stat = 1 << 1,
+  Timer = 1 << 2,
+  Serial = 1 << 3,
+  Joypad = 1 << 4,
+}
+
 pub struct Bus {
   ram: [u8; 64 * 1024],
+  model: Model,
+  pub ppu: Ppu,
+  pub joypad: Joypad,
+  pub timer: Timer,
+  pub apu: Apu,
+  pub serial: Serial,
+  // Banked ROM (used for GBS files, cartridges are plain memory): 0x4000-0x7FFF
+  // shows bank `rom_bank`, picked by writing to 0x2000-0x3FFF.
+  rom: Option<Vec<u8>>,
+  rom_bank: usize,
+  // T-cycles since power on.
+  cycles: u64,
+  // Sound register writes are logged here, with `cycles` when the log started.
+  vgm: Option<(VgmLog, u64)>,
 }
 
-#[allow(dead_code)]
 impl Bus {
-  pub fn new() -> Self {
-    Bus { ram: [0; 64 * 1024] }
+  pub fn new(model: Model) -> Self {
+    let mut bus = Bus {
+      ram: [0; 64 * 1024],
+      model,
+      ppu: Ppu::new(model),
+      joypad: Joypad::new(),
+      timer: Timer::new(0),
+      apu: Apu::new(model),
+      serial: Serial::new(model),
+      rom: None,
+      rom_bank: 1,
+      cycles: 0,
+      vgm: None,
+    };
+    for (addr, data) in model.io_registers() {
+      match addr {
+        // Only the register value, the boot ROM never started a DMA transfer.
+        0xFF46 => bus.ram[addr as usize] = data,
+        // Where DIV's internal counter is at, a write would reset it.
+        0xFF04 => bus.timer = Timer::new((data as u16) << 8),
+        _ => bus.write(addr, data),
+      }
+    }
+    bus
+  }
+
+  pub fn cycles(&self) -> u64 {
+    self.cycles
   }
 
   pub fn read(&self, addr: u16) -> u8 {
-    self.ram[addr as usize]
+    match addr {
+      0x0000..=0x7FFF if self.rom.is_some() => self.read_rom(addr),
+      0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read(addr),
+      0xFF00 => self.joypad.read(),
+      0xFF01..=0xFF02 => self.serial.read(addr),
+      0xFF04..=0xFF07 => self.timer.read(addr),
+      0xFF0F => self.ram[addr as usize] | 0xE0,
+      0xFF10..=0xFF3F => self.apu.read(addr),
+      _ => self.ram[addr as usize],
+    }
   }
 
   pub fn write(&mut self, addr: u16, data: u8) {
-    self.ram[addr as usize] = data;
+    match addr {
+      0x2000..=0x3FFF if self.rom.is_some() => self.rom_bank = (data as usize).max(1),
+      0x0000..=0x7FFF if self.rom.is_some() => {},
+      0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write(addr, data),
+      0xFF00 => self.joypad.write(data),
+      0xFF01..=0xFF02 => self.serial.write(addr, data),
+      0xFF04..=0xFF07 => self.timer.write(addr, data),
+      0xFF10..=0xFF3F => {
+        self.apu.write(addr, data);
+        if let Some((log, start)) = &mut self.vgm {
+          log.write(self.cycles - *start, addr, data);
+        }
+      },
+      0xFF46 => {
+        // OAM DMA: copy 160 bytes from 0xXX00 into OAM. Done all at once rather
+        // than over the 160 machine cycles it takes on hardware.
+        self.ram[addr as usize] = data;
+        let source = (data as u16) << 8;
+        for i in 0..0xA0 {
+          let byte = self.read(source + i);
+          self.ppu.write(0xFE00 + i, byte);
+        }
+      },
+      _ => self.ram[addr as usize] = data,
+    }
+  }
+
+  // Map a banked ROM into 0x0000-0x7FFF, starting with bank 1.
+  pub fn map_rom(&mut self, rom: Vec<u8>) {
+    self.rom = Some(rom);
+    self.rom_bank = 1;
+  }
+
+  fn read_rom(&self, addr: u16) -> u8 {
+    let rom = self.rom.as_ref().unwrap();
+    let offset = match addr {
+      0x0000..=0x3FFF => addr as usize,
+      _ => self.rom_bank * 0x4000 + (addr as usize - 0x4000),
+    };
+    // Banks past the end of the ROM read as open bus.
+    rom.get(offset).copied().unwrap_or(0xFF)
+  }
+
+  // Log every write to the sound registers from now on, meant to start right after
+  // boot. A VGM player starts with the APU off, so the log begins with the state
+  // the boot ROM left it in (most sound registers are write-only, they can't be read back).
+  pub fn record_vgm(&mut self, path: &str) {
+    let mut log = VgmLog::create(path);
+    log.write(0, 0xFF26, 0x80);
+    for (addr, data) in self.model.io_registers() {
+      if let 0xFF10..=0xFF25 = addr {
+        log.write(0, addr, data);
+      }
+    }
+    for addr in 0xFF30..=0xFF3F {
+      log.write(0, addr, self.apu.read(addr));
+    }
+    self.vgm = Some((log, self.cycles));
+  }
+
+  // End the VGM log, if there's one.
+  pub fn finish_vgm(&mut self) {
+    if let Some((mut log, start)) = self.vgm.take() {
+      log.finish(self.cycles - start);
+    }
   }
-}
 
+  // Advance every peripheral by the given number of T-cycles.
+  pub fn tick(&mut self, cycles: u32) {
+    self.cycles += cycles as u64;
+    self.ppu.tick(cycles);
+    self.timer.tick(cycles);
+    self.apu.tick(cycles);
+    self.serial.tick(cycles);
+    for _ in 0..self.timer.take_apu_clocks() {
+      self.apu.frame_sequencer();
+    }
+    self.ram[0xFF0F] |= self.ppu.take_interrupts();
+    self.ram[0xFF0F] |= self.timer.take_interrupts();
+    self.ram[0xFF0F] |= self.joypad.take_interrupts();
+    self.ram[0xFF0F] |= self.serial.take_interrupts();
+  }
+
+  // Clear a requested interrupt without the CPU servicing it. Returns whether it was requested.
+  pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) -> bool {
+    let bit = interrupt as u8;
+    let requested = self.ram[0xFF0F] & bit != 0;
+    self.ram[0xFF0F] &= !bit;
+    requested
+  }
+}
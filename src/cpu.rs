@@ -1,5 +1,7 @@
 use crate::bus::Bus;
+use crate::model::Model;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU<'a> {
  // Registers
   af: [u8; 2],
@@ -8,6 +10,11 @@ pub struct CPU<'a> {
   pc: u16,
 
   ime: bool,
+  // Instructions left until an EI takes effect.
+  ime_delay: u8,
+  halted: bool,
+  // T-cycles of the current instruction already ticked by its memory accesses.
+  ticked: u32,
 
   // Data BUS
   bus: Option<&'a mut Bus>,
@@ -22,13 +29,17 @@ enum Flag {
 
 impl<'a> CPU<'a> {
 
-  pub fn new() -> Self {
-    CPU { af: [0x01, 0xB0], 
-          bcdehl: [0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D], 
+  pub fn new(model: Model, game: &[u8]) -> Self {
+    let (af, bcdehl) = model.boot_registers(game);
+    CPU { af, 
+          bcdehl, 
           sp: 0xFFFE,
-          pc: 0,
+          pc: 0x100,
           bus: None,
           ime: false,
+          ime_delay: 0,
+          halted: false,
+          ticked: 0,
         }
   }
 
@@ -83,25 +94,28 @@ impl<'a> CPU<'a> {
     self.bcdehl[5]
   }
 
+  fn get_af(&self) -> u16 {
+    let hi = self.get_a() as u16;
+    let lo = self.get_f() as u16;
+    (hi << 8) + lo
+  }
+
   fn get_bc(&self) -> u16 {
     let hi = self.get_b() as u16;
     let lo = self.get_c() as u16;
-    let bc = (hi << 8) + lo;
-    bc
+    (hi << 8) + lo
   }
 
   fn get_de(&self) -> u16 {
     let hi = self.get_d() as u16;
     let lo = self.get_e() as u16;
-    let de = (hi << 8) + lo;
-    de
+    (hi << 8) + lo
   }
 
   fn get_hl(&self) -> u16 {
     let hi = self.get_h() as u16;
     let lo = self.get_l() as u16;
-    let hl = (hi << 8) + lo;
-    hl
+    (hi << 8) + lo
   }
 
   // REGISTER SETTERS
@@ -139,28 +153,28 @@ impl<'a> CPU<'a> {
   
   fn set_bc(&mut self, d: u16) {
     let hi = (d >> 8) as u8;
-    let lo = (d << 8) as u8;
+    let lo = d as u8;
     self.set_b(hi);
     self.set_c(lo);
   }
   
   fn set_de(&mut self, d: u16) {
     let hi = (d >> 8) as u8;
-    let lo = (d << 8) as u8;
+    let lo = d as u8;
     self.set_d(hi);
     self.set_e(lo);
   }
   
   fn set_hl(&mut self, d: u16) {
     let hi = (d >> 8) as u8;
-    let lo = (d << 8) as u8;
+    let lo = d as u8;
     self.set_h(hi);
     self.set_l(lo);
   }
 
   fn set_af(&mut self, d: u16) {
     let hi = (d >> 8) as u8;
-    let lo = (d << 8) as u8;
+    let lo = d as u8;
     self.set_a(hi);
     self.set_f(lo);
   }
@@ -185,12 +199,121 @@ impl<'a> CPU<'a> {
     self.bus = Some(bus); 
   }
 
+  pub fn bus(&self) -> &Bus {
+    match &self.bus {
+      Some(b) => b,
+      None => panic!("No bus connected!"),
+    }
+  }
+
+  pub fn bus_mut(&mut self) -> &mut Bus {
+    match &mut self.bus {
+      Some(b) => b,
+      None => panic!("No bus connected!"),
+    }
+  }
+
+  // Let the rest of the hardware catch up with the CPU.
+  pub fn tick(&mut self, cycles: u32) {
+    self.bus_mut().tick(cycles);
+  }
+
+  // Every memory access of an instruction takes a machine cycle, starting with the
+  // access: the hardware sees it after the cycles that come before it, not all at once.
+  fn read_cycle(&mut self, a: u16) -> u8 {
+    let d = self.read(a);
+    self.tick_cycle();
+    d
+  }
+
+  fn write_cycle(&mut self, a: u16, d: u8) {
+    self.write(a, d);
+    self.tick_cycle();
+  }
+
+  fn tick_cycle(&mut self) {
+    self.tick(4);
+    self.ticked += 4;
+  }
+
+  // Tick what's left of the `cycles` the instruction took, after its memory accesses.
+  // Returns all the cycles it took.
+  pub fn finish(&mut self, cycles: u32) -> u32 {
+    let ticked = std::mem::take(&mut self.ticked);
+    if cycles > ticked {
+      self.tick(cycles - ticked);
+    }
+    cycles.max(ticked)
+  }
+
   pub fn fetch(&mut self) -> u8 {
-    let opcode = self.read(self.pc);
+    let opcode = self.read_cycle(self.pc);
     self.pc += 1;
     opcode
   }
 
+  pub fn is_halted(&self) -> bool {
+    self.halted
+  }
+
+  pub fn pc(&self) -> u16 {
+    self.pc
+  }
+
+  // B, C, D, E, H and L, e.g. for test ROMs that report results in them.
+  pub fn bcdehl(&self) -> [u8; 6] {
+    self.bcdehl
+  }
+
+  // Start over at `pc` with the given stack and A, e.g. to run the routines of a GBS file.
+  pub fn reset(&mut self, pc: u16, sp: u16, a: u8) {
+    self.pc = pc;
+    self.sp = sp;
+    self.set_a(a);
+    self.ime = false;
+    self.ime_delay = 0;
+    self.halted = false;
+  }
+
+  // Call `addr` from outside the program, like a CALL instruction would: push PC and jump.
+  pub fn call(&mut self, addr: u16) {
+    self.sp -= 1;
+    self.write(self.sp, msb(self.pc));
+    self.sp -= 1;
+    self.write(self.sp, lsb(self.pc));
+    self.pc = addr;
+    self.halted = false;
+  }
+
+  // Service the highest priority interrupt that is both requested (IF) and enabled (IE).
+  // Any pending interrupt wakes the CPU from HALT, even with IME off.
+  // Returns the machine cycles spent dispatching.
+  pub fn interrupt(&mut self) -> u8 {
+    if self.ime_delay > 0 {
+      self.ime_delay -= 1;
+      if self.ime_delay == 0 {
+        self.ime = true;
+      }
+    }
+    let pending = self.read(0xFFFF) & self.read(0xFF0F) & 0x1F;
+    if pending == 0 {
+      return 0;
+    }
+    self.halted = false;
+    if !self.ime {
+      return 0;
+    }
+    let bit = pending.trailing_zeros() as u16;
+    self.write(0xFF0F, self.read(0xFF0F) & !(1 << bit));
+    self.ime = false;
+    // Two cycles go by before the return address is pushed.
+    self.tick_cycle();
+    self.tick_cycle();
+    self.push(self.pc);
+    self.pc = 0x40 + bit * 8;
+    5
+  }
+
   pub fn decode(&mut self, opcode: u8) -> (u8, fn(&mut CPU<'a>)) {
     match opcode {
       0x00 => (1, CPU::nop),
@@ -217,7 +340,7 @@ impl<'a> CPU<'a> {
       0x15 => (1, CPU::dec_d),
       0x16 => (2, CPU::ld_d_n),
       0x17 => (1, CPU::rla),
-      0x18 => (4, CPU::jr_n),
+      0x18 => (3, CPU::jr_n),
       0x19 => (2, CPU::add_hl_de),
       0x1A => (2, CPU::ld_a_mde),
       0x1B => (2, CPU::dec_de),
@@ -225,7 +348,7 @@ impl<'a> CPU<'a> {
       0x1D => (1, CPU::dec_e),
       0x1E => (2, CPU::ld_e_n),
       0x1F => (1, CPU::rra),
-      0x20 => (3, CPU::jr_nz_n), //TBD: 12/8
+      0x20 => (2, CPU::jr_nz_n), //TBD: 12/8
       0x21 => (3, CPU::ld_hl_nn),
       0x22 => (2, CPU::ldi_mhl_a),
       0x23 => (2, CPU::inc_hl),
@@ -233,7 +356,7 @@ impl<'a> CPU<'a> {
       0x25 => (1, CPU::dec_h),
       0x26 => (2, CPU::ld_h_n),
       0x27 => (1, CPU::daa),
-      0x28 => (3, CPU::jr_z_n), // TBD: 12/8
+      0x28 => (2, CPU::jr_z_n), // TBD: 12/8
       0x29 => (2, CPU::add_hl_hl),
       0x2A => (2, CPU::ldi_a_mhl),
       0x2B => (2, CPU::dec_hl),
@@ -241,7 +364,7 @@ impl<'a> CPU<'a> {
       0x2D => (1, CPU::dec_l),
       0x2E => (2, CPU::ld_l_n),
       0x2F => (1, CPU::cpl),
-      0x30 => (3, CPU::jr_nc_n), //TBD: 12/8
+      0x30 => (2, CPU::jr_nc_n), //TBD: 12/8
       0x31 => (3, CPU::ld_sp_nn),
       0x32 => (2, CPU::ldd_mhl_a),
       0x33 => (2, CPU::inc_sp),
@@ -249,7 +372,7 @@ impl<'a> CPU<'a> {
       0x35 => (3, CPU::dec_mhl),
       0x36 => (3, CPU::ld_mhl_n),
       0x37 => (1, CPU::scf),
-      0x38 => (3, CPU::jr_c_n), //TBD: 12/8
+      0x38 => (2, CPU::jr_c_n), //TBD: 12/8
       0x39 => (2, CPU::add_hl_sp),
       0x3A => (2, CPU::ldd_a_mhl),
       0x3B => (2, CPU::dec_sp),
@@ -312,7 +435,7 @@ impl<'a> CPU<'a> {
       0x74 => (2, CPU::ld_mhl_h),
       0x75 => (2, CPU::ld_mhl_l),
       0x76 => (1, CPU::halt),
-      0x77 => (1, CPU::ld_mhl_a),
+      0x77 => (2, CPU::ld_mhl_a),
       0x78 => (1, CPU::ld_a_b),
       0x79 => (1, CPU::ld_a_c),
       0x7A => (1, CPU::ld_a_d),
@@ -385,35 +508,35 @@ impl<'a> CPU<'a> {
       0xBD => (1, CPU::cp_a_l),
       0xBE => (2, CPU::cp_a_mhl),
       0xBF => (1, CPU::cp_a_a),
-      0xC0 => (5, CPU::ret_nz),
+      0xC0 => (2, CPU::ret_nz),
       0xC1 => (3, CPU::pop_bc),
-      0xC2 => (4, CPU::jp_nz_nn),
+      0xC2 => (3, CPU::jp_nz_nn),
       0xC3 => (4, CPU::jp_nn),
-      0xC4 => (6, CPU::call_nz_nn),
+      0xC4 => (3, CPU::call_nz_nn),
       0xC5 => (4, CPU::push_bc),
       0xC6 => (2, CPU::add_a_n),
       0xC7 => (4, CPU::rst_00),
-      0xC8 => (5, CPU::ret_z),
+      0xC8 => (2, CPU::ret_z),
       0xC9 => (4, CPU::ret),
-      0xCA => (4, CPU::jp_z_nn),
-      0xCB => (1, CPU::prefix),
-      0xCC => (6, CPU::call_z_nn),
+      0xCA => (3, CPU::jp_z_nn),
+      0xCB => (2, CPU::prefix),
+      0xCC => (3, CPU::call_z_nn),
       0xCD => (6, CPU::call_nn),
       0xCE => (2, CPU::adc_a_n),
       0xCF => (4, CPU::rst_08),
-      0xD0 => (5, CPU::ret_nc),
+      0xD0 => (2, CPU::ret_nc),
       0xD1 => (3, CPU::pop_de),
-      0xD2 => (4, CPU::jp_nc_nn),
+      0xD2 => (3, CPU::jp_nc_nn),
       0xD3 => (0, CPU::unimplemented),
-      0xD4 => (6, CPU::call_nc_nn),
+      0xD4 => (3, CPU::call_nc_nn),
       0xD5 => (4, CPU::push_de),
       0xD6 => (2, CPU::sub_a_n),
       0xD7 => (4, CPU::rst_10),
-      0xD8 => (5, CPU::ret_c),
+      0xD8 => (2, CPU::ret_c),
       0xD9 => (4, CPU::reti),
-      0xDA => (4, CPU::jp_c_nn),
+      0xDA => (3, CPU::jp_c_nn),
       0xDB => (1, CPU::unimplemented),
-      0xDC => (6, CPU::call_c_nn),
+      0xDC => (3, CPU::call_c_nn),
       0xDD => (6, CPU::unimplemented),
       0xDE => (2, CPU::sbc_a_n),
       0xDF => (4, CPU::rst_18),
@@ -460,20 +583,106 @@ impl<'a> CPU<'a> {
   */
 
   fn unimplemented(&mut self) {
-    println!("unimplemented opcode");
+    panic!("Unimplemented opcode {:02X} at {:04X}", self.read(self.pc - 1), self.pc - 1);
   }
 
   fn nop(&mut self) {}
 
+  // CONTROL FLOW. Taking a conditional branch costs an extra machine cycle, the
+  // decode table has the cycles for when it's not taken.
+  fn jr_if(&mut self, condition: bool) {
+    let offset = self.read_cycle(self.pc) as i8;
+    self.pc += 1;
+    if condition {
+      self.tick_cycle();
+      self.pc = self.pc.wrapping_add(offset as u16);
+    }
+  }
+
+  fn jp_if(&mut self, condition: bool) {
+    let nn = self.read_nn();
+    if condition {
+      self.tick_cycle();
+      self.pc = nn;
+    }
+  }
+
+  fn call_if(&mut self, condition: bool) {
+    let nn = self.read_nn();
+    if condition {
+      self.tick_cycle();
+      self.push(self.pc);
+      self.pc = nn;
+    }
+  }
+
+  // Checking the condition takes a cycle of its own.
+  fn ret_if(&mut self, condition: bool) {
+    self.tick_cycle();
+    if condition {
+      self.pc = self.pop();
+      self.tick_cycle();
+    }
+  }
+
+  fn rst(&mut self, addr: u16) {
+    self.tick_cycle();
+    self.push(self.pc);
+    self.pc = addr;
+  }
+
+  // The 16-bit operand following the opcode.
+  fn read_nn(&mut self) -> u16 {
+    let lo = self.read_cycle(self.pc) as u16;
+    self.pc += 1;
+    let hi = self.read_cycle(self.pc) as u16;
+    self.pc += 1;
+    (hi << 8) + lo
+  }
+
+  fn push(&mut self, d: u16) {
+    self.sp -= 1;
+    self.write_cycle(self.sp, msb(d));
+    self.sp -= 1;
+    self.write_cycle(self.sp, lsb(d));
+  }
+
+  fn pop(&mut self) -> u16 {
+    let lo = self.read_cycle(self.sp) as u16;
+    self.sp += 1;
+    let hi = self.read_cycle(self.sp) as u16;
+    self.sp += 1;
+    (hi << 8) + lo
+  }
+
+  // Operands of the CB instructions: B, C, D, E, H, L, (HL) and A.
+  fn read_operand(&mut self, operand: u8) -> u8 {
+    match operand {
+      0..=5 => self.bcdehl[operand as usize],
+      6 => self.read_cycle(self.get_hl()),
+      _ => self.get_a(),
+    }
+  }
+
+  fn write_operand(&mut self, operand: u8, d: u8) {
+    match operand {
+      0..=5 => self.bcdehl[operand as usize] = d,
+      6 => self.write_cycle(self.get_hl(), d),
+      _ => self.set_a(d),
+    }
+  }
+
   fn ld_bc_nn(&mut self) {
-    self.set_c(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_c(byte);
     self.pc += 1;
-    self.set_b(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_b(byte);
     self.pc += 1;
   }
 
   fn ld_mbc_a(&mut self) {
-    self.write(self.get_bc(), self.get_a());
+    self.write_cycle(self.get_bc(), self.get_a());
   }
 
   fn inc_bc(&mut self) {
@@ -506,9 +715,9 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_b_n(&mut self) {
-    self.set_b(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_b(byte);
     self.pc += 1;
-    println!("b set to {}", self.get_b());
   }
 
   fn rlca(&mut self) {
@@ -524,15 +733,15 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_nn_sp(&mut self) {
-    let lo = self.read(self.pc) as u16;
+    let lo = self.read_cycle(self.pc) as u16;
     self.pc += 1;
-    let hi = self.read(self.pc) as u16;
+    let hi = self.read_cycle(self.pc) as u16;
     self.pc += 1;
     let nn = (hi << 8) + lo;
     let lo = lsb(self.sp);
     let hi = msb(self.sp);
-    self.write(nn, lo);
-    self.write(nn + 1, hi);
+    self.write_cycle(nn, lo);
+    self.write_cycle(nn + 1, hi);
   }
 
   fn add_hl_bc(&mut self) {
@@ -546,7 +755,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_a_mbc(&mut self) {
-    self.set_a(self.read(self.get_bc()));
+    let byte = self.read_cycle(self.get_bc());
+    self.set_a(byte);
   }
 
   fn dec_bc(&mut self) {
@@ -578,12 +788,13 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_c_n(&mut self) {
-    self.set_c(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_c(byte);
     self.pc += 1;
   }
 
   fn rrca(&mut self) {
-    let is_carry = self.get_a() % 2 != 0;
+    let is_carry = self.get_a() & 1 != 0;
     self.set_flag(Flag::C, is_carry); 
     self.set_flag(Flag::Z, false);
     self.set_flag(Flag::N, false);
@@ -598,14 +809,16 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_de_nn(&mut self) {
-    self.set_e(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_e(byte);
     self.pc += 1;
-    self.set_d(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_d(byte);
     self.pc += 1;
   }
 
   fn ld_mde_a(&mut self) {
-    self.write(self.get_de(), self.get_a());
+    self.write_cycle(self.get_de(), self.get_a());
   }
 
   fn inc_de(&mut self) {
@@ -637,7 +850,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_d_n(&mut self) {
-    self.set_b(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_d(byte);
     self.pc += 1;
   }
 
@@ -654,12 +868,7 @@ impl<'a> CPU<'a> {
   }
 
   fn jr_n(&mut self) {
-    let signed_int = self.read(self.pc);
-    if signed_int > 127 {
-      self.pc += (signed_int & 0x7F) as u16;
-    } else {
-      self.pc -= (signed_int & 0x7F) as u16;
-    }
+    self.jr_if(true);
   }
 
   fn add_hl_de(&mut self) {
@@ -673,7 +882,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_a_mde(&mut self) {
-    self.set_a(self.read(self.get_de()));
+    let byte = self.read_cycle(self.get_de());
+    self.set_a(byte);
   }
 
   fn dec_de(&mut self) {
@@ -697,12 +907,13 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_e_n(&mut self) {
-    self.set_e(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_e(byte);
     self.pc += 1;
   }
 
   fn rra(&mut self) {
-    let is_carry = self.get_a() % 2 != 0;
+    let is_carry = self.get_a() & 1 != 0;
     let last_bit = self.get_flag(Flag::C);
     self.set_flag(Flag::C, is_carry); 
     self.set_flag(Flag::Z, false);
@@ -714,26 +925,20 @@ impl<'a> CPU<'a> {
   }
  
   fn jr_nz_n(&mut self) {
-    let signed_int = self.read(self.pc);
-    self.pc += 1;
-    if !self.get_flag(Flag::Z) {
-      if signed_int > 127 {
-        self.pc += (signed_int & 0x7F) as u16;
-      } else {
-        self.pc -= (signed_int & 0x7F) as u16;
-      }
-    }
+    self.jr_if(!self.get_flag(Flag::Z));
   }
 
   fn ld_hl_nn(&mut self) {
-    self.set_l(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_l(byte);
     self.pc += 1;
-    self.set_h(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_h(byte);
     self.pc += 1;
   }
 
   fn ldi_mhl_a(&mut self) {
-    self.write(self.get_hl(), self.get_a());
+    self.write_cycle(self.get_hl(), self.get_a());
     self.set_hl(self.get_hl() + 1);
   }
 
@@ -758,7 +963,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_h_n(&mut self) {
-    self.set_h(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_h(byte);
     self.pc += 1;
   }
 
@@ -781,15 +987,7 @@ impl<'a> CPU<'a> {
   }
 
   fn jr_z_n(&mut self) {
-    let signed_int = self.read(self.pc);
-    self.pc += 1;
-    if self.get_flag(Flag::Z) {
-      if signed_int > 127 {
-        self.pc += (signed_int & 0x7F) as u16;
-      } else {
-        self.pc -= (signed_int & 0x7F) as u16;
-      }
-    }
+    self.jr_if(self.get_flag(Flag::Z));
   }
 
   fn add_hl_hl(&mut self) {
@@ -803,7 +1001,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ldi_a_mhl(&mut self) {
-    self.set_a(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_a(byte);
     self.set_hl(self.get_hl() + 1);
   }
 
@@ -828,7 +1027,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_l_n(&mut self) {
-    self.set_l(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_l(byte);
     self.pc += 1;
   }
 
@@ -840,26 +1040,18 @@ impl<'a> CPU<'a> {
 
   // HERE
   fn jr_nc_n(&mut self) {
-    let signed_int = self.read(self.pc);
-    self.pc += 1;
-    if self.get_flag(Flag::N) && self.get_flag(Flag::C) {
-      if signed_int > 127 {
-        self.pc += (signed_int & 0x7F) as u16;
-      } else {
-        self.pc -= (signed_int & 0x7F) as u16;
-      }
-    }
+    self.jr_if(!self.get_flag(Flag::C));
   }
 
   fn ld_sp_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
+    let lo = self.read_cycle(self.pc) as u16;
     self.pc += 1;
-    let hi = self.read(self.pc) as u16;
+    let hi = self.read_cycle(self.pc) as u16;
     self.sp = (hi << 8) + lo;
   }
 
   fn ldd_mhl_a(&mut self) {
-    self.write(self.get_hl(), self.get_a());
+    self.write_cycle(self.get_hl(), self.get_a());
     self.set_hl(self.get_hl() - 1);
   }
 
@@ -868,7 +1060,7 @@ impl<'a> CPU<'a> {
   }
 
   fn inc_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let is_half_carry = (((byte & 0xf) + (1 & 0xf)) & 0x10) != 0;
     let res = byte + 1;
     self.set_flag(Flag::Z, res == 0);
@@ -877,7 +1069,7 @@ impl<'a> CPU<'a> {
   }
 
   fn dec_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let is_half_carry = (((byte & 0xf) - (1 & 0xf)) & 0x10) != 0;
     let res = byte - 1;
     self.set_flag(Flag::Z, res == 0);
@@ -886,7 +1078,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_mhl_n(&mut self) {
-    self.write(self.get_hl(), self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.write_cycle(self.get_hl(), byte);
     self.pc += 1;
   }
 
@@ -897,15 +1090,7 @@ impl<'a> CPU<'a> {
   }
 
   fn jr_c_n(&mut self) {
-    let signed_int = self.read(self.pc);
-    self.pc += 1;
-    if self.get_flag(Flag::C) {
-      if signed_int > 127 {
-        self.pc += (signed_int & 0x7F) as u16;
-      } else {
-        self.pc -= (signed_int & 0x7F) as u16;
-      }
-    }
+    self.jr_if(self.get_flag(Flag::C));
   }
 
   fn add_hl_sp(&mut self) {
@@ -919,7 +1104,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ldd_a_mhl(&mut self) {
-    self.set_a(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_a(byte);
     self.set_hl(self.get_hl() - 1);
   }
 
@@ -944,7 +1130,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_a_n(&mut self) {
-    self.set_a(self.read(self.pc));
+    let byte = self.read_cycle(self.pc);
+    self.set_a(byte);
     self.pc += 1;
   }
 
@@ -979,7 +1166,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_b_mhl(&mut self) {
-    self.set_b(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_b(byte);
   }
 
   fn ld_b_a(&mut self) {
@@ -1011,7 +1199,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_c_mhl(&mut self) {
-    self.set_c(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_c(byte);
   }
 
   fn ld_c_a(&mut self) {
@@ -1043,7 +1232,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_d_mhl(&mut self) {
-    self.set_d(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_d(byte);
   }
 
   fn ld_d_a(&mut self) {
@@ -1075,7 +1265,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_e_mhl(&mut self) {
-    self.set_e(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_e(byte);
   }
 
   fn ld_e_a(&mut self) {
@@ -1107,7 +1298,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_h_mhl(&mut self) {
-    self.set_h(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_h(byte);
   }
 
   fn ld_h_a(&mut self) {
@@ -1139,7 +1331,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_l_mhl(&mut self) {
-    self.set_l(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_l(byte);
   }
 
   fn ld_l_a(&mut self) {
@@ -1147,35 +1340,35 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_mhl_b(&mut self) {
-    self.write(self.get_hl(), self.get_b());
+    self.write_cycle(self.get_hl(), self.get_b());
   }
 
   fn ld_mhl_c(&mut self) {
-    self.write(self.get_hl(), self.get_c());
+    self.write_cycle(self.get_hl(), self.get_c());
   }
 
   fn ld_mhl_d(&mut self) {
-    self.write(self.get_hl(), self.get_d());
+    self.write_cycle(self.get_hl(), self.get_d());
   }
 
   fn ld_mhl_e(&mut self) {
-    self.write(self.get_hl(), self.get_e());
+    self.write_cycle(self.get_hl(), self.get_e());
   }
 
   fn ld_mhl_h(&mut self) {
-    self.write(self.get_hl(), self.get_h());
+    self.write_cycle(self.get_hl(), self.get_h());
   }
 
   fn ld_mhl_l(&mut self) {
-    self.write(self.get_hl(), self.get_l());
+    self.write_cycle(self.get_hl(), self.get_l());
   }
 
   fn halt(&mut self) {
-    //TODO: implement halt
+    self.halted = true;
   }
 
   fn ld_mhl_a(&mut self) {
-    self.write(self.get_hl(), self.get_a());
+    self.write_cycle(self.get_hl(), self.get_a());
   }
 
   fn ld_a_b(&mut self) {
@@ -1203,7 +1396,8 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_a_mhl(&mut self) {
-    self.set_a(self.read(self.get_hl()));
+    let byte = self.read_cycle(self.get_hl());
+    self.set_a(byte);
   }
 
   fn ld_a_a(&mut self) {
@@ -1277,7 +1471,7 @@ impl<'a> CPU<'a> {
   }
 
   fn add_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let total = (self.get_a() + byte) as u16;
     let res = (total & 0xFF) as u8;
     let is_half_carry = (((self.get_a() & 0xf) + (byte & 0xf)) & 0x10) != 0;
@@ -1372,7 +1566,7 @@ impl<'a> CPU<'a> {
   }
 
   fn adc_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let mut total = (self.get_a() + byte) as u16;
     if self.get_flag(Flag::C) { total += 1 }
     let res = (total & 0xFF) as u8;
@@ -1463,7 +1657,7 @@ impl<'a> CPU<'a> {
   }
 
   fn sub_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let total = (self.get_a() - byte) as u16;
     let res = (total & 0xFF) as u8;
     let is_half_carry = (((self.get_a() & 0xf) - (byte & 0xf)) & 0x10) != 0;
@@ -1591,7 +1785,7 @@ impl<'a> CPU<'a> {
   }
 
   fn sbc_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let mut total = (self.get_a() - byte) as u16;
     if self.get_flag(Flag::C) {
       if total == 0 {
@@ -1682,7 +1876,7 @@ impl<'a> CPU<'a> {
   }
 
   fn and_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let res = self.get_a() & byte;
     self.set_flag(Flag::C, false);
     self.set_flag(Flag::H, true);
@@ -1755,7 +1949,7 @@ impl<'a> CPU<'a> {
   }
 
   fn xor_a_mhl(&mut self) {
-    let res = self.get_a() ^ self.read(self.get_hl());
+    let res = self.get_a() ^ self.read_cycle(self.get_hl());
     self.set_flag(Flag::C, false);
     self.set_flag(Flag::H, false);
     self.set_flag(Flag::N, false);
@@ -1827,7 +2021,7 @@ impl<'a> CPU<'a> {
   }
 
   fn or_a_mhl(&mut self) {
-    let res = self.get_a() | self.read(self.get_hl());
+    let res = self.get_a() | self.read_cycle(self.get_hl());
     self.set_flag(Flag::C, false);
     self.set_flag(Flag::H, false);
     self.set_flag(Flag::N, false);
@@ -1893,7 +2087,7 @@ impl<'a> CPU<'a> {
   }
 
   fn cp_a_mhl(&mut self) {
-    let byte = self.read(self.get_hl());
+    let byte = self.read_cycle(self.get_hl());
     let is_half_carry = (((self.get_a() & 0xf) - (byte & 0xf)) & 0x10) != 0;
     self.set_flag(Flag::C, self.get_a() < byte);
     self.set_flag(Flag::H, is_half_carry);
@@ -1909,69 +2103,33 @@ impl<'a> CPU<'a> {
   }
   
   fn ret_nz(&mut self) {
-    if !self.get_flag(Flag::Z) {
-      let lo = self.read(self.sp) as u16;
-      self.sp += 1;
-      let hi = self.read(self.sp) as u16;
-      self.sp += 1;
-      let nn = (hi << 8) + lo;
-      self.pc = nn;
-    }
+    self.ret_if(!self.get_flag(Flag::Z));
   }
 
   fn pop_bc(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
+    let nn = self.pop();
     self.set_bc(nn);
   }
 
   fn jp_nz_nn(&mut self) {
-    let lo = self.read(self.pc);
-    self.pc += 1;
-    let hi = self.read(self.pc);
-    self.pc += 1;
-    if !self.get_flag(Flag::Z) {
-      self.pc = (hi << 8 + lo) as u16;
-    }
+    self.jp_if(!self.get_flag(Flag::Z));
   }
 
   fn jp_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    let nn = (hi << 8) + lo;
-    self.pc = nn;
+    self.jp_if(true);
   }
 
   fn call_nz_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    let nn = (hi << 8) + lo;
-    if !self.get_flag(Flag::Z) {
-      self.pc = nn;
-      self.sp -= 1;
-      self.write(self.sp, hi as u8);
-      self.sp -= 1;
-      self.write(self.sp, lo as u8);
-      self.pc = nn;
-    }
+    self.call_if(!self.get_flag(Flag::Z));
   }
 
   fn push_bc(&mut self) {
-    self.sp -= 1;
-    self.write(self.sp, self.get_b());
-    self.sp -= 1;
-    self.write(self.sp, self.get_c());
+    self.tick_cycle();
+    self.push(self.get_bc());
   }
 
   fn add_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let res = (self.get_a() + byte) as u16;
     let is_half_carry = (((self.get_a() & 0xf) + (byte & 0xf)) & 0x10) != 0;
@@ -1982,81 +2140,68 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_00(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x00 as u16;
+    self.rst(0x00);
   }
 
   fn ret_z(&mut self) {
-    if self.get_flag(Flag::Z) {
-      let lo = self.read(self.sp) as u16;
-      self.sp += 1;
-      let hi = self.read(self.sp) as u16;
-      self.sp += 1;
-      let nn = (hi << 8) + lo;
-      self.pc = nn;
-    }
+    self.ret_if(self.get_flag(Flag::Z));
   }
 
   fn ret(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
-    self.pc = nn;
+    self.pc = self.pop();
   }
 
   fn jp_z_nn(&mut self) {
-    let lo = self.read(self.pc);
-    self.pc += 1;
-    let hi = self.read(self.pc);
-    self.pc += 1;
-    if self.get_flag(Flag::Z) {
-      self.pc = (hi << 8 + lo) as u16;
-    }
+    self.jp_if(self.get_flag(Flag::Z));
   }
 
   fn prefix(&mut self) {
-    println!("prefix");
+    let opcode = self.read_cycle(self.pc);
+    self.pc += 1;
+    let operand = opcode & 0x07;
+    let bit = (opcode >> 3) & 0x07;
+    let value = self.read_operand(operand);
+    match opcode >> 6 {
+      // BIT only reads its operand.
+      1 => {
+        self.set_flag(Flag::Z, value & (1 << bit) == 0);
+        self.set_flag(Flag::N, false);
+        self.set_flag(Flag::H, true);
+      },
+      2 => self.write_operand(operand, value & !(1 << bit)),
+      3 => self.write_operand(operand, value | (1 << bit)),
+      // Rotates and shifts, picked by the same 3 bits.
+      _ => {
+        let carry = self.get_flag(Flag::C) as u8;
+        let (res, carry_out) = match bit {
+          0 => (value.rotate_left(1), value >> 7),
+          1 => (value.rotate_right(1), value & 1),
+          2 => (value << 1 | carry, value >> 7),
+          3 => (value >> 1 | carry << 7, value & 1),
+          4 => (value << 1, value >> 7),
+          5 => (value >> 1 | (value & 0x80), value & 1),
+          6 => (value.rotate_left(4), 0),
+          _ => (value >> 1, value & 1),
+        };
+        self.set_flag(Flag::Z, res == 0);
+        self.set_flag(Flag::N, false);
+        self.set_flag(Flag::H, false);
+        self.set_flag(Flag::C, carry_out != 0);
+        self.write_operand(operand, res);
+      },
+    }
   }
 
   fn call_z_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    let nn = (hi << 8) + lo;
-    if self.get_flag(Flag::Z) {
-      self.pc = nn;
-      self.sp -= 1;
-      self.write(self.sp, hi as u8);
-      self.sp -= 1;
-      self.write(self.sp, lo as u8);
-      self.pc = nn;
-    }
+    self.call_if(self.get_flag(Flag::Z));
   }
 
   fn call_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    let nn = ((hi << 8) + lo) as u16;
-    self.pc += 1;
-    self.pc = nn;
-    self.sp -= 1;
-    self.write(self.sp, hi as u8);
-    self.sp -= 1;
-    self.write(self.sp, lo as u8);
-    self.pc = nn;
+    self.call_if(true);
   }
 
   fn adc_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let mut total = (self.get_a() + byte) as u16;
     if self.get_flag(Flag::C) { total += 1 }
@@ -2070,70 +2215,33 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_08(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x08 as u16;
+    self.rst(0x08);
   }
 
   fn ret_nc(&mut self) {
-    if !self.get_flag(Flag::C) {
-      let lo = self.read(self.sp) as u16;
-      self.sp += 1;
-      let hi = self.read(self.sp) as u16;
-      self.sp += 1;
-      let nn = (hi << 8) + lo;
-      self.pc = nn;
-    }
+    self.ret_if(!self.get_flag(Flag::C));
   }
 
   fn pop_de(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
+    let nn = self.pop();
     self.set_de(nn);
   }
 
   fn jp_nc_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    if !self.get_flag(Flag::C) {
-      self.pc = (hi << 8) + lo;
-    }
+    self.jp_if(!self.get_flag(Flag::C));
   }
 
   fn call_nc_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    let nn = (hi << 8) + lo;
-    if !self.get_flag(Flag::C) {
-      self.pc = nn;
-      self.sp -= 1;
-      self.write(self.sp, hi as u8);
-      self.sp -= 1;
-      self.write(self.sp, lo as u8);
-      self.pc = nn;
-    }
+    self.call_if(!self.get_flag(Flag::C));
   }
 
   fn push_de(&mut self) {
-    self.sp -= 1;
-    self.write(self.sp, self.get_d());
-    self.sp -= 1;
-    self.write(self.sp, self.get_e());
+    self.tick_cycle();
+    self.push(self.get_de());
   }
 
   fn sub_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let total = (self.get_a() - byte) as u16;
     let res = (total & 0xFF) as u8;
@@ -2146,64 +2254,28 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_10(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x10 as u16;
+    self.rst(0x10);
   }
 
   fn ret_c(&mut self) {
-    if self.get_flag(Flag::C) {
-      let lo = self.read(self.sp) as u16;
-      self.sp += 1;
-      let hi = self.read(self.sp) as u16;
-      self.sp += 1;
-      let nn = (hi << 8) + lo;
-      self.pc = nn;
-    }
+    self.ret_if(self.get_flag(Flag::C));
   }
 
   fn reti(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
-    self.pc = nn;
+    self.pc = self.pop();
     self.ime = true;
   }
 
   fn jp_c_nn(&mut self) {
-    let lo = self.read(self.pc);
-    self.pc += 1;
-    let hi = self.read(self.pc);
-    self.pc += 1;
-    if self.get_flag(Flag::C) {
-      self.pc = (hi << 8 + lo) as u16;
-    }
+    self.jp_if(self.get_flag(Flag::C));
   }
 
   fn call_c_nn(&mut self) {
-    let lo = self.read(self.pc) as u16;
-    self.pc += 1;
-    let hi = self.read(self.pc) as u16;
-    self.pc += 1;
-    let nn = (hi << 8) + lo;
-    if self.get_flag(Flag::C) {
-      self.pc = nn;
-      self.sp -= 1;
-      self.write(self.sp, hi as u8);
-      self.sp -= 1;
-      self.write(self.sp, lo as u8);
-      self.pc = nn;
-    }
+    self.call_if(self.get_flag(Flag::C));
   }
 
   fn sbc_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let mut total = (self.get_a() - byte) as u16;
     if self.get_flag(Flag::C) {
@@ -2223,46 +2295,34 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_18(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x18 as u16;
+    self.rst(0x18);
   }
 
   fn ldh_n_a(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let addr = 0xFF00 + byte as u16;
-    self.write(addr, self.get_a());
+    self.write_cycle(addr, self.get_a());
   }
 
   fn pop_hl(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
+    let nn = self.pop();
     self.set_hl(nn);
   }
 
   fn ld_mc_a(&mut self) {
     self.pc += 1;
     let addr = 0xFF00 + self.get_c() as u16;
-    self.write(addr, self.get_a());
+    self.write_cycle(addr, self.get_a());
   }
 
   fn push_hl(&mut self) {
-    self.sp -= 1;
-    self.write(self.sp, self.get_h());
-    self.sp -= 1;
-    self.write(self.sp, self.get_l());
+    self.tick_cycle();
+    self.push(self.get_hl());
   }
 
   fn and_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     let res = self.get_a() & byte;
     self.set_flag(Flag::C, false);
     self.set_flag(Flag::H, true);
@@ -2272,17 +2332,11 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_20(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x20 as u16;
+    self.rst(0x20);
   }
 
   fn add_sp_rn(&mut self) {
-    let signed_int = self.read(self.pc);
+    let signed_int = self.read_cycle(self.pc);
     if signed_int > 127 {
       self.sp += (signed_int & 0x7F) as u16;
     } else {
@@ -2295,16 +2349,16 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_mnn_a(&mut self) {
-    let hi = self.read(self.pc);
+    let lo = self.read_cycle(self.pc);
     self.pc += 1;
-    let lo = self.read(self.pc);
+    let hi = self.read_cycle(self.pc);
     self.pc += 1;
-    let addr = (hi << 8 + lo) as u16;
-    self.write(addr, self.get_a());
+    let addr = ((hi as u16) << 8) + lo as u16;
+    self.write_cycle(addr, self.get_a());
   }
 
   fn xor_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let res = self.get_a() ^ byte;
     self.set_flag(Flag::C, false);
@@ -2315,50 +2369,42 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_28(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x28 as u16;
+    self.rst(0x28);
   }
 
   fn ldh_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let addr = 0xFF00 + byte as u16;
-    self.set_a(self.read(addr));
+    let byte = self.read_cycle(addr);
+    self.set_a(byte);
   }
 
   fn pop_af(&mut self) {
-    let lo = self.read(self.sp) as u16;
-    self.sp += 1;
-    let hi = self.read(self.sp) as u16;
-    self.sp += 1;
-    let nn = (hi << 8) + lo;
-    self.set_af(nn);
+    // The low 4 bits of F are always 0.
+    let nn = self.pop();
+    self.set_af(nn & 0xFFF0);
   }
 
   fn ld_a_mc(&mut self) {
     self.pc += 1;
     let addr = 0xFF00 + self.get_c() as u16;
-    self.set_a(self.read(addr));
+    let byte = self.read_cycle(addr);
+    self.set_a(byte);
   }
 
   fn di(&mut self) {
     self.ime = false;
+    self.ime_delay = 0;
   }
 
   fn push_af(&mut self) {
-    self.sp -= 1;
-    self.write(self.sp, self.get_a());
-    self.sp -= 1;
-    self.write(self.sp, self.get_f());
+    self.tick_cycle();
+    self.push(self.get_af());
   }
 
   fn or_a_n(&mut self) {
-    let res = self.get_a() | self.read(self.pc);
+    let res = self.get_a() | self.read_cycle(self.pc);
     self.pc += 1;
     self.set_flag(Flag::C, false);
     self.set_flag(Flag::H, false);
@@ -2368,17 +2414,11 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_30(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x30 as u16;
+    self.rst(0x30);
   }
 
   fn ld_hl_sprn(&mut self) {
-    let signed_int = self.read(self.pc);
+    let signed_int = self.read_cycle(self.pc);
     if signed_int > 127 {
       self.set_hl(self.sp + (signed_int & 0x7F) as u16);
     } else {
@@ -2391,20 +2431,22 @@ impl<'a> CPU<'a> {
   }
 
   fn ld_a_mnn(&mut self) {
-    let hi = self.read(self.pc);
+    let lo = self.read_cycle(self.pc);
     self.pc += 1;
-    let lo = self.read(self.pc);
+    let hi = self.read_cycle(self.pc);
     self.pc += 1;
-    let addr = (hi << 8 + lo) as u16;
-    self.set_a(self.read(addr));
+    let addr = ((hi as u16) << 8) + lo as u16;
+    let byte = self.read_cycle(addr);
+    self.set_a(byte);
   }
 
   fn ei(&mut self) {
-    self.ime = true;
+    // IME only goes up after the next instruction.
+    self.ime_delay = 2;
   }
 
   fn cp_a_n(&mut self) {
-    let byte = self.read(self.pc);
+    let byte = self.read_cycle(self.pc);
     self.pc += 1;
     let is_half_carry = (((self.get_a() & 0xf) - (byte & 0xf)) & 0x10) != 0;
     self.set_flag(Flag::C, self.get_a() < byte);
@@ -2414,13 +2456,7 @@ impl<'a> CPU<'a> {
   }
 
   fn rst_38(&mut self) {
-    self.sp -= 1;
-    let hi = (self.pc >> 8) as u8;
-    let lo = (self.pc & 0xFF) as u8;
-    self.write(self.sp, hi);
-    self.sp -= 1;
-    self.write(self.sp, lo);
-    self.pc = 0x38u16;
+    self.rst(0x38);
   }
 }
 
@@ -2431,3 +2467,176 @@ fn msb(d: u16) -> u8 {
 fn lsb(d: u16) -> u8 {
   (d & 0xFF) as u8
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A CPU running `code` from WRAM.
+  fn with_cpu<F: FnOnce(&mut CPU)>(code: &[u8], f: F) {
+    let mut bus = Bus::new(Model::DMG);
+    let mut cpu = CPU::new(Model::DMG, &[0; 0x150]);
+    cpu.connect_bus(&mut bus);
+    for (i, &byte) in code.iter().enumerate() {
+      cpu.write(0xC000 + i as u16, byte);
+    }
+    cpu.pc = 0xC000;
+    cpu.sp = 0xD000;
+    f(&mut cpu);
+  }
+
+  // One instruction, like Gameboy::step. Returns its T-cycles, after checking the
+  // rest of the hardware ran for as long.
+  fn step(cpu: &mut CPU) -> u32 {
+    let start = cpu.bus().cycles();
+    let opcode = cpu.fetch();
+    let (duration, op) = cpu.decode(opcode);
+    op(cpu);
+    let cycles = cpu.finish(duration as u32 * 4);
+    assert_eq!(cpu.bus().cycles() - start, cycles as u64);
+    cycles
+  }
+
+  fn run_at(cpu: &mut CPU, pc: u16) -> u32 {
+    cpu.pc = pc;
+    step(cpu)
+  }
+
+  #[test]
+  fn jr_timing_and_offsets() {
+    // jr nz,+5; jr -2; jr nc,-128
+    with_cpu(&[0x20, 0x05, 0x18, 0xFE, 0x30, 0x80], |cpu| {
+      cpu.set_flag(Flag::Z, true);
+      assert_eq!(run_at(cpu, 0xC000), 8);
+      assert_eq!(cpu.pc, 0xC002);
+      cpu.set_flag(Flag::Z, false);
+      assert_eq!(run_at(cpu, 0xC000), 12);
+      assert_eq!(cpu.pc, 0xC007);
+
+      assert_eq!(run_at(cpu, 0xC002), 12);
+      assert_eq!(cpu.pc, 0xC002);
+
+      // N has nothing to do with it.
+      cpu.set_flag(Flag::N, true);
+      cpu.set_flag(Flag::C, true);
+      assert_eq!(run_at(cpu, 0xC004), 8);
+      assert_eq!(cpu.pc, 0xC006);
+      cpu.set_flag(Flag::C, false);
+      assert_eq!(run_at(cpu, 0xC004), 12);
+      assert_eq!(cpu.pc, 0xC006 - 128);
+    });
+  }
+
+  #[test]
+  fn jp_timing() {
+    // jp z,0x1234; jp 0x1234
+    with_cpu(&[0xCA, 0x34, 0x12, 0xC3, 0x34, 0x12], |cpu| {
+      cpu.set_flag(Flag::Z, false);
+      assert_eq!(run_at(cpu, 0xC000), 12);
+      assert_eq!(cpu.pc, 0xC003);
+      cpu.set_flag(Flag::Z, true);
+      assert_eq!(run_at(cpu, 0xC000), 16);
+      assert_eq!(cpu.pc, 0x1234);
+      assert_eq!(run_at(cpu, 0xC003), 16);
+      assert_eq!(cpu.pc, 0x1234);
+    });
+  }
+
+  #[test]
+  fn call_and_ret_timing() {
+    // call c,0xC010; call 0xC010; ... 0xC010: ret c; ret
+    let mut code = vec![0xDC, 0x10, 0xC0, 0xCD, 0x10, 0xC0];
+    code.resize(0x10, 0);
+    code.extend_from_slice(&[0xD8, 0xC9]);
+    with_cpu(&code, |cpu| {
+      cpu.set_flag(Flag::C, false);
+      assert_eq!(run_at(cpu, 0xC000), 12);
+      assert_eq!((cpu.pc, cpu.sp), (0xC003, 0xD000));
+      assert_eq!(run_at(cpu, 0xC010), 8);
+      assert_eq!((cpu.pc, cpu.sp), (0xC011, 0xD000));
+
+      cpu.set_flag(Flag::C, true);
+      assert_eq!(run_at(cpu, 0xC000), 24);
+      // The return address goes on the stack.
+      assert_eq!((cpu.pc, cpu.sp), (0xC010, 0xCFFE));
+      assert_eq!((cpu.read(0xCFFF), cpu.read(0xCFFE)), (0xC0, 0x03));
+      assert_eq!(step(cpu), 20);
+      assert_eq!((cpu.pc, cpu.sp), (0xC003, 0xD000));
+
+      assert_eq!(step(cpu), 24);
+      assert_eq!(run_at(cpu, 0xC011), 16);
+      assert_eq!((cpu.pc, cpu.sp), (0xC006, 0xD000));
+    });
+  }
+
+  #[test]
+  fn push_and_pop() {
+    // push bc; pop de; rst 0x38
+    with_cpu(&[0xC5, 0xD1, 0xFF], |cpu| {
+      cpu.set_bc(0x1234);
+      assert_eq!(step(cpu), 16);
+      assert_eq!(step(cpu), 12);
+      assert_eq!(cpu.get_de(), 0x1234);
+      assert_eq!(step(cpu), 16);
+      assert_eq!(cpu.pc, 0x38);
+      assert_eq!(cpu.pop(), 0xC003);
+    });
+  }
+
+  #[test]
+  fn cb_instructions() {
+    // swap a; rl c; sra d; bit 7,h; res 0,(hl); bit 0,(hl); set 7,(hl)
+    with_cpu(&[0xCB, 0x37, 0xCB, 0x11, 0xCB, 0x2A, 0xCB, 0x7C, 0xCB, 0x86, 0xCB, 0x46, 0xCB, 0xFE], |cpu| {
+      cpu.set_a(0x12);
+      assert_eq!(step(cpu), 8);
+      assert_eq!(cpu.get_a(), 0x21);
+
+      cpu.set_c(0x80);
+      cpu.set_flag(Flag::C, true);
+      step(cpu);
+      assert_eq!(cpu.get_c(), 0x01);
+      assert!(cpu.get_flag(Flag::C));
+      assert!(!cpu.get_flag(Flag::Z));
+
+      cpu.set_d(0x81);
+      step(cpu);
+      assert_eq!(cpu.get_d(), 0xC0);
+      assert!(cpu.get_flag(Flag::C));
+
+      cpu.set_hl(0xC100);
+      assert_eq!(step(cpu), 8);
+      assert!(!cpu.get_flag(Flag::Z) && cpu.get_flag(Flag::H));
+
+      cpu.write(0xC100, 0x0F);
+      assert_eq!(step(cpu), 16);
+      assert_eq!(cpu.read(0xC100), 0x0E);
+      assert_eq!(step(cpu), 12);
+      assert!(cpu.get_flag(Flag::Z));
+      assert_eq!(step(cpu), 16);
+      assert_eq!(cpu.read(0xC100), 0x8E);
+    });
+  }
+
+  #[test]
+  fn ei_takes_effect_after_the_next_instruction() {
+    // ei; nop; nop
+    with_cpu(&[0xFB, 0x00, 0x00], |cpu| {
+      cpu.write(0xFFFF, 0x01);
+      cpu.write(0xFF0F, 0x01);
+      step(cpu);
+      assert_eq!(cpu.interrupt(), 0);
+      step(cpu);
+      assert_eq!(cpu.interrupt(), 5);
+      assert_eq!(cpu.pc, 0x40);
+      assert_eq!(cpu.pop(), 0xC002);
+    });
+  }
+
+  #[test]
+  #[should_panic(expected = "Unimplemented opcode D3 at C000")]
+  fn unimplemented_opcodes_panic() {
+    with_cpu(&[0xD3], |cpu| {
+      step(cpu);
+    });
+  }
+}
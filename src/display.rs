@@ -1,16 +1,46 @@
 extern crate piston_window;
 use piston_window::*;
+use piston_window::texture::{CreateTexture, Format, UpdateTexture};
+use gameboi::SoundToggle;
+use gameboi::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT, SHADES};
+use gameboi::pacer::Speed;
+use gameboi::joypad;
+use gameboi::apu::Scope;
+use crate::keymap::Keymap;
+
+// 3x5 pixel hex digits for the register dump, one row per byte, high bit on the left.
+const HEX_FONT: [[u8; 5]; 16] = [
+  [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 3, 1, 7],
+  [5, 5, 7, 1, 1], [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 2, 2],
+  [7, 5, 7, 5, 7], [7, 5, 7, 1, 7], [2, 5, 7, 5, 5], [6, 5, 6, 5, 6],
+  [7, 4, 4, 4, 7], [6, 5, 5, 5, 6], [7, 4, 6, 4, 7], [7, 4, 6, 4, 4],
+];
 
 pub struct Display {
-  window: PistonWindow
+  window: PistonWindow,
+  texture_context: G2dTextureContext,
+  texture: G2dTexture,
+  // GameBoys shown side by side.
+  screens: usize,
+  // RGBA copy of the last frame, uploaded to the texture on render.
+  pixels: Vec<u8>,
+  // Oscilloscope overlay, drawn over the screen while there's one.
+  scope: Option<Scope>,
 }
 
 impl Display {
-  pub fn new() -> Self {
-    let window_settings = WindowSettings::new("RustBoy", [160 * 3, 144 * 3])
+  pub fn new(scale: u32, screens: usize) -> Self {
+    let width = (SCREEN_WIDTH * screens) as u32;
+    let window_settings = WindowSettings::new("RustBoy", [width * scale, SCREEN_HEIGHT as u32 * scale])
       .exit_on_esc(true);
-    let window: PistonWindow = window_settings.build().unwrap();
-    Display { window: window, }
+    let mut window: PistonWindow = window_settings.build().unwrap();
+    let mut texture_context = window.create_texture_context();
+    let pixels = vec![0xFF; SCREEN_WIDTH * screens * SCREEN_HEIGHT * 4];
+    // Nearest neighbour, so scaled up pixels stay sharp.
+    let settings = TextureSettings::new().filter(Filter::Nearest);
+    let size = [width, SCREEN_HEIGHT as u32];
+    let texture = CreateTexture::create(&mut texture_context, Format::Rgba8, &pixels, size, &settings).unwrap();
+    Display { window, texture_context, texture, screens, pixels, scope: None }
   }
 
   pub fn set_title(&self, title: &str) {
@@ -21,12 +51,123 @@ impl Display {
     self.window.next()
   }
 
+  // F1-F4 pick the emulation speed.
+  pub fn speed_change(&self, e: &Event) -> Option<Speed> {
+    match e.press_args() {
+      Some(Button::Keyboard(Key::F1)) => Some(Speed::Normal),
+      Some(Button::Keyboard(Key::F2)) => Some(Speed::FastForward),
+      Some(Button::Keyboard(Key::F3)) => Some(Speed::SlowMotion),
+      Some(Button::Keyboard(Key::F4)) => Some(Speed::Uncapped),
+      _ => None,
+    }
+  }
+
+  // F5-F8 mute channel 1-4, F9-F12 solo them, Tab shows the oscilloscope.
+  pub fn sound_change(&self, e: &Event) -> Option<SoundToggle> {
+    match e.press_args() {
+      Some(Button::Keyboard(Key::F5)) => Some(SoundToggle::Mute(0)),
+      Some(Button::Keyboard(Key::F6)) => Some(SoundToggle::Mute(1)),
+      Some(Button::Keyboard(Key::F7)) => Some(SoundToggle::Mute(2)),
+      Some(Button::Keyboard(Key::F8)) => Some(SoundToggle::Mute(3)),
+      Some(Button::Keyboard(Key::F9)) => Some(SoundToggle::Solo(0)),
+      Some(Button::Keyboard(Key::F10)) => Some(SoundToggle::Solo(1)),
+      Some(Button::Keyboard(Key::F11)) => Some(SoundToggle::Solo(2)),
+      Some(Button::Keyboard(Key::F12)) => Some(SoundToggle::Solo(3)),
+      Some(Button::Keyboard(Key::Tab)) => Some(SoundToggle::Scope),
+      _ => None,
+    }
+  }
+
+  // A GameBoy button pressed (true) or released (false), with the keymap of one GameBoy.
+  pub fn button_change(&self, e: &Event, keymap: &Keymap) -> Option<(joypad::Button, bool)> {
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+      return keymap.button(key).map(|button| (button, true));
+    }
+    if let Some(Button::Keyboard(key)) = e.release_args() {
+      return keymap.button(key).map(|button| (button, false));
+    }
+    None
+  }
+
+  // Take a finished frame from the PPU of each GameBoy (one shade per pixel), and
+  // the scope to draw over the first one.
+  pub fn update(&mut self, framebuffers: &[Vec<u8>], scope: Option<Scope>) {
+    let row_pixels = SCREEN_WIDTH * self.screens;
+    for (screen, framebuffer) in framebuffers.iter().enumerate() {
+      for (i, shade) in framebuffer.iter().enumerate() {
+        let offset = ((i / SCREEN_WIDTH) * row_pixels + screen * SCREEN_WIDTH + i % SCREEN_WIDTH) * 4;
+        self.pixels[offset..offset + 3].copy_from_slice(&SHADES[*shade as usize]);
+        self.pixels[offset + 3] = 0xFF;
+      }
+    }
+    self.scope = scope;
+  }
+
   pub fn refresh(&mut self, e: &Event) {
- 		self.window.draw_2d(e, |c, g, _| {
-		  clear([0.0, 0.0, 0.0, 0.0], g);
-      rectangle([1.0, 0.0, 0.0, 1.0], // red
-                [0.0, 0.0, 100.0, 100.0], // rectangle
-                 c.transform, g);
+    if e.render_args().is_none() {
+      return;
+    }
+    let width = SCREEN_WIDTH * self.screens;
+    let size = [width as u32, SCREEN_HEIGHT as u32];
+    UpdateTexture::update(&mut self.texture, &mut self.texture_context, Format::Rgba8, &self.pixels, [0, 0], size).unwrap();
+
+    let texture = &self.texture;
+    let texture_context = &mut self.texture_context;
+    let scope = &self.scope;
+    let screens_width = width as f64;
+    self.window.draw_2d(e, |c, g, device| {
+      texture_context.encoder.flush(device);
+      clear([0.0, 0.0, 0.0, 1.0], g);
+      // Biggest integer scale that fits, centered with black bars around it.
+      let [width, height] = c.get_view_size();
+      let scale = (width / screens_width).min(height / SCREEN_HEIGHT as f64).floor().max(1.0);
+      let x = ((width - screens_width * scale) / 2.0).floor();
+      let y = ((height - SCREEN_HEIGHT as f64 * scale) / 2.0).floor();
+      image(texture, c.transform.trans(x, y).scale(scale, scale), g);
+      if let Some(scope) = scope {
+        let transform = c.transform.trans(x, y).scale(scale, scale);
+        draw_scope(scope, transform, g);
+      }
     });
   }
 }
+
+// Over the 160x144 screen: a strip per channel with its waveform on the left and
+// its registers on the right, then a last row with NR50-NR52. Muted channels are dimmed.
+fn draw_scope<G: Graphics>(scope: &Scope, transform: math::Matrix2d, g: &mut G) {
+  const STRIP: f64 = 144.0 / 5.0;
+  const WAVE_WIDTH: f64 = 100.0;
+  rectangle([0.0, 0.0, 0.0, 0.75], [0.0, 0.0, 160.0, 144.0], transform, g);
+  for (channel, wave) in scope.waves.iter().enumerate() {
+    let top = channel as f64 * STRIP;
+    let color = if scope.audible[channel] { [0.3, 1.0, 0.3, 1.0] } else { [0.3, 0.4, 0.3, 1.0] };
+    // Level 15 at the top of the strip, 0 at the bottom.
+    let y = |level: u8| top + 2.0 + (15 - level) as f64 / 15.0 * (STRIP - 4.0);
+    let step = WAVE_WIDTH / wave.len().max(1) as f64;
+    for (i, pair) in wave.windows(2).enumerate() {
+      let x = i as f64 * step;
+      line(color, 0.25, [x, y(pair[0]), x + step, y(pair[1])], transform, g);
+    }
+    let registers = &scope.registers[channel * 5..channel * 5 + 5];
+    draw_hex(registers, WAVE_WIDTH + 4.0, top + 2.0, color, transform, g);
+  }
+  draw_hex(&scope.registers[0x14..], WAVE_WIDTH + 4.0, 4.0 * STRIP + 2.0, [1.0; 4], transform, g);
+}
+
+// Bytes as hex, two rows of up to 3 bytes so they fit next to the waveform.
+fn draw_hex<G: Graphics>(bytes: &[u8], x: f64, y: f64, color: [f32; 4], transform: math::Matrix2d, g: &mut G) {
+  for (i, byte) in bytes.iter().enumerate() {
+    let byte_x = x + (i % 3) as f64 * 18.0;
+    let byte_y = y + (i / 3) as f64 * 8.0;
+    for (n, digit) in [byte >> 4, byte & 0x0F].iter().enumerate() {
+      for (row, bits) in HEX_FONT[*digit as usize].iter().enumerate() {
+        for column in 0..3 {
+          if bits & (4 >> column) != 0 {
+            let pixel = [byte_x + n as f64 * 4.0 + column as f64, byte_y + row as f64, 1.0, 1.0];
+            rectangle(color, pixel, transform, g);
+          }
+        }
+      }
+    }
+  }
+}
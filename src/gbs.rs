@@ -0,0 +1,242 @@
+use std::sync::mpsc::{Receiver, TryRecvError};
+use crate::{Gameboy, CYCLES_PER_FRAME};
+use crate::audio::Audio;
+use crate::bus::Interrupt;
+use crate::pacer::{Pacer, Speed};
+
+const HEADER_SIZE: usize = 0x70;
+// The routines of the file are called with this as return address. Nothing can
+// run from this unusable area, so reaching it means the routine is done.
+const RETURN_ADDRESS: u16 = 0xFEA0;
+
+// GBS sound file: the music code and data ripped out of a game, with a header
+// saying where to load it and which routines to call.
+pub struct Gbs {
+  pub songs: u8,
+  // 1-based
+  pub first_song: u8,
+  load: u16,
+  init: u16,
+  play: u16,
+  sp: u16,
+  tma: u8,
+  tac: u8,
+  pub title: String,
+  pub author: String,
+  pub copyright: String,
+  data: Vec<u8>,
+}
+
+impl Gbs {
+  pub fn is_gbs(file: &[u8]) -> bool {
+    file.starts_with(b"GBS")
+  }
+
+  pub fn parse(file: &[u8]) -> Self {
+    if file.len() < HEADER_SIZE || !Gbs::is_gbs(file) {
+      panic!("Invalid GBS file");
+    }
+    if file[0x03] != 1 {
+      panic!("Unsupported GBS version {}", file[0x03]);
+    }
+    let word = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+    let text = |offset: usize| {
+      let field = &file[offset..offset + 32];
+      let end = field.iter().position(|&b| b == 0).unwrap_or(32);
+      String::from_utf8_lossy(&field[..end]).into_owned()
+    };
+    let gbs = Gbs {
+      songs: file[0x04],
+      first_song: file[0x05].max(1),
+      load: word(0x06),
+      init: word(0x08),
+      play: word(0x0A),
+      sp: word(0x0C),
+      tma: file[0x0E],
+      tac: file[0x0F],
+      title: text(0x10),
+      author: text(0x30),
+      copyright: text(0x50),
+      data: file[HEADER_SIZE..].to_vec(),
+    };
+    if gbs.load < 0x400 {
+      panic!("Invalid GBS load address {:04X}", gbs.load);
+    }
+    gbs
+  }
+
+  // The data at its load address in a banked ROM image. RST instructions jump to
+  // the same offset from the load address, interrupt vectors just return.
+  pub fn rom(&self) -> Vec<u8> {
+    let size = (self.load as usize + self.data.len()).max(0x8000);
+    let mut rom = vec![0; size.div_ceil(0x4000) * 0x4000];
+    rom[self.load as usize..self.load as usize + self.data.len()].copy_from_slice(&self.data);
+    for rst in (0x00..0x40).step_by(8) {
+      let target = self.load + rst as u16;
+      rom[rst..rst + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]);
+    }
+    for vector in (0x40..0x68).step_by(8) {
+      // RETI
+      rom[vector] = 0xD9;
+    }
+    rom
+  }
+
+  // Play is called from the timer interrupt when TAC enables the timer, otherwise on VBlank.
+  fn timer_driven(&self) -> bool {
+    self.tac & 0x04 != 0
+  }
+}
+
+// Sent from the terminal to the player.
+pub enum Command {
+  Next,
+  Previous,
+  Song(u8),
+  // Channels 0-3
+  Mute(usize),
+  Solo(usize),
+  Quit,
+}
+
+// One line typed in the terminal: `n`, `p`, a song number, `m1`-`m4`/`s1`-`s4` or `q`.
+pub fn parse_command(line: &str) -> Option<Command> {
+  let line = line.trim();
+  let channel = |n: &str| n.parse::<usize>().ok().filter(|n| (1..=4).contains(n)).map(|n| n - 1);
+  match line {
+    "n" => Some(Command::Next),
+    "p" => Some(Command::Previous),
+    "q" => Some(Command::Quit),
+    _ if line.starts_with('m') => channel(&line[1..]).map(Command::Mute),
+    _ if line.starts_with('s') => channel(&line[1..]).map(Command::Solo),
+    song => song.parse().ok().map(Command::Song),
+  }
+}
+
+// Runs a GBS file on the CPU, timer and APU. There's no cartridge or screen,
+// the player calls the file's routines itself.
+pub struct Player {
+  gbs: Gbs,
+  song: u8,
+  // A routine is running, anything else has to wait for it to return.
+  busy: bool,
+  play_pending: bool,
+}
+
+impl Player {
+  pub fn new(gb: &mut Gameboy, gbs: Gbs, song: Option<u8>) -> Self {
+    gb.cpu.bus_mut().map_rom(gbs.rom());
+    let song = song.unwrap_or(gbs.first_song);
+    let mut player = Player { gbs, song, busy: false, play_pending: false };
+    player.start_song(gb, song);
+    player
+  }
+
+  pub fn song(&self) -> u8 {
+    self.song
+  }
+
+  pub fn gbs(&self) -> &Gbs {
+    &self.gbs
+  }
+
+  // Reset the sound hardware and call init with the (0-based) song number in A.
+  pub fn start_song(&mut self, gb: &mut Gameboy, song: u8) {
+    self.song = song.clamp(1, self.gbs.songs.max(1));
+    let bus = gb.cpu.bus_mut();
+    bus.write(0xFF26, 0x00);
+    bus.write(0xFF26, 0x80);
+    bus.write(0xFF25, 0xFF);
+    bus.write(0xFF24, 0x77);
+    bus.write(0xFF06, self.gbs.tma);
+    bus.write(0xFF07, self.gbs.tac);
+    bus.acknowledge_interrupt(Interrupt::Timer);
+    gb.cpu.reset(RETURN_ADDRESS, self.gbs.sp, self.song - 1);
+    self.call(gb, self.gbs.init);
+    self.play_pending = false;
+  }
+
+  pub fn handle(&mut self, gb: &mut Gameboy, command: Command) {
+    let song = match command {
+      Command::Next => self.song % self.gbs.songs.max(1) + 1,
+      Command::Previous => if self.song > 1 { self.song - 1 } else { self.gbs.songs },
+      Command::Song(song) => song,
+      Command::Mute(channel) => return gb.cpu.bus_mut().apu.toggle_mute(channel),
+      Command::Solo(channel) => return gb.cpu.bus_mut().apu.toggle_solo(channel),
+      // Up to whoever runs the player.
+      Command::Quit => return,
+    };
+    self.start_song(gb, song);
+  }
+
+  // Run a frame's worth of cycles, calling play whenever it's due.
+  pub fn run_frame(&mut self, gb: &mut Gameboy) {
+    if !self.gbs.timer_driven() {
+      self.play_pending = true;
+    }
+    let mut cycles = 0;
+    while cycles < CYCLES_PER_FRAME {
+      if gb.cpu.pc() == RETURN_ADDRESS {
+        self.busy = false;
+      }
+      if self.play_pending && !self.busy {
+        self.play_pending = false;
+        self.call(gb, self.gbs.play);
+      }
+      cycles += if self.busy {
+        gb.step()
+      } else {
+        gb.cpu.tick(4);
+        4
+      };
+      if self.gbs.timer_driven() && gb.cpu.bus_mut().acknowledge_interrupt(Interrupt::Timer) {
+        self.play_pending = true;
+      }
+    }
+  }
+
+  // Interrupts are left to the player, the file's code never gets them.
+  fn call(&mut self, gb: &mut Gameboy, addr: u16) {
+    gb.cpu.bus_mut().write(0xFFFF, 0x00);
+    gb.cpu.call(addr);
+    self.busy = true;
+  }
+}
+
+// Play in real time, switching songs as commands come in, until told to quit or
+// the commands stop coming (end of input).
+pub fn run(gb: &mut Gameboy, player: &mut Player, commands: Receiver<Command>, audio: &mut dyn Audio) {
+  let mut pacer = Pacer::new(Speed::Normal);
+  gb.play_audio(audio);
+  loop {
+    loop {
+      match commands.try_recv() {
+        Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+        Ok(command) => {
+          let song = player.song();
+          player.handle(gb, command);
+          if player.song() != song {
+            print_song(player);
+          }
+        },
+        Err(TryRecvError::Empty) => break,
+      }
+    }
+    player.run_frame(gb);
+    gb.play_audio(audio);
+    pacer.wait();
+  }
+}
+
+// Play a fixed number of frames as fast as possible, e.g. to record them.
+pub fn run_headless(gb: &mut Gameboy, player: &mut Player, frames: u32, audio: &mut dyn Audio) {
+  gb.play_audio(audio);
+  for _ in 0..frames {
+    player.run_frame(gb);
+    gb.play_audio(audio);
+  }
+}
+
+pub fn print_song(player: &Player) {
+  println!("Song {}/{}", player.song(), player.gbs().songs);
+}
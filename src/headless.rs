@@ -0,0 +1,116 @@
+use std::fs;
+use crate::Gameboy;
+use crate::lockstep;
+use crate::audio::Audio;
+use crate::joypad::Button;
+use crate::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use crate::image::{self, numbered_path};
+
+pub struct Options {
+  pub frames: u32,
+  // Input script, see InputEvent.
+  pub input: Option<String>,
+  // Where the final frame is saved.
+  pub screenshot: Option<String>,
+  // Also save every Nth frame, next to the screenshot with the frame number appended.
+  pub screenshot_every: Option<u32>,
+}
+
+// One line of an input script: `<frame> <press|release> <button>`, e.g. `120 press start`.
+// Empty lines and lines starting with # are ignored.
+struct InputEvent {
+  frame: u32,
+  button: Button,
+  pressed: bool,
+}
+
+// Run the emulator for a fixed number of frames without a window.
+pub fn run(gb: &mut Gameboy, options: &Options, audio: &mut dyn Audio) {
+  let script = match &options.input {
+    Some(path) => parse_script(&fs::read_to_string(path).unwrap()),
+    None => Vec::new(),
+  };
+  gb.play_audio(audio);
+
+  for frame in 0..options.frames {
+    for event in script.iter().filter(|e| e.frame == frame) {
+      gb.cpu.bus_mut().joypad.set(event.button, event.pressed);
+    }
+    gb.run_frame();
+    gb.play_audio(audio);
+
+    if let (Some(path), Some(every)) = (&options.screenshot, options.screenshot_every) {
+      if (frame + 1) % every == 0 {
+        save_png(&numbered_path(path, frame + 1), &[gb.cpu.bus().ppu.framebuffer().to_vec()]);
+      }
+    }
+  }
+
+  if let Some(path) = &options.screenshot {
+    save_png(path, &[gb.cpu.bus().ppu.framebuffer().to_vec()]);
+  }
+}
+
+// Same for linked GameBoys, see lockstep.rs. Input goes to the first one, and
+// screenshots have every screen side by side.
+pub fn run_linked(gbs: &mut [Gameboy], options: &Options, audio: &mut dyn Audio) {
+  let script = match &options.input {
+    Some(path) => parse_script(&fs::read_to_string(path).unwrap()),
+    None => Vec::new(),
+  };
+  gbs[0].play_audio(audio);
+
+  // What's on screen now, in case no frame runs.
+  let mut screens: Vec<Vec<u8>> = gbs.iter().map(|gb| gb.cpu.bus().ppu.framebuffer().to_vec()).collect();
+  for frame in 0..options.frames {
+    for event in script.iter().filter(|e| e.frame == frame) {
+      gbs[0].cpu.bus_mut().joypad.set(event.button, event.pressed);
+    }
+    screens = lockstep::run_frame(gbs);
+    gbs[0].play_audio(audio);
+
+    if let (Some(path), Some(every)) = (&options.screenshot, options.screenshot_every) {
+      if (frame + 1) % every == 0 {
+        save_png(&numbered_path(path, frame + 1), &screens);
+      }
+    }
+  }
+
+  if let Some(path) = &options.screenshot {
+    save_png(path, &screens);
+  }
+}
+
+fn parse_script(script: &str) -> Vec<InputEvent> {
+  let mut events = Vec::new();
+  for (number, line) in script.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+      panic!("Invalid input script line {}: '{}'", number + 1, line);
+    }
+    let pressed = match fields[1] {
+      "press" => true,
+      "release" => false,
+      action => panic!("Invalid input script line {}: unknown action '{}'", number + 1, action),
+    };
+    events.push(InputEvent {
+      frame: fields[0].parse().unwrap_or_else(|_| panic!("Invalid input script line {}: bad frame", number + 1)),
+      button: fields[2].parse().unwrap_or_else(|e: String| panic!("Invalid input script line {}: {}", number + 1, e)),
+      pressed,
+    });
+  }
+  events
+}
+
+// One or more screens, side by side.
+fn save_png(path: &str, framebuffers: &[Vec<u8>]) {
+  let shades: Vec<u8> = (0..SCREEN_HEIGHT)
+    .flat_map(|y| framebuffers.iter().flat_map(move |framebuffer| &framebuffer[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]))
+    .copied()
+    .collect();
+  image::save_png(path, SCREEN_WIDTH * framebuffers.len(), &shades);
+}
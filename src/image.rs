@@ -0,0 +1,21 @@
+use std::fs::File;
+use std::io::BufWriter;
+use crate::path::suffixed_path;
+use crate::ppu::SHADES;
+
+// out.png -> out-120.png
+pub fn numbered_path(path: &str, number: u32) -> String {
+  suffixed_path(path, &number.to_string())
+}
+
+// Shades (0-3, row by row) as an RGB PNG.
+pub fn save_png(path: &str, width: usize, shades: &[u8]) {
+  let file = File::create(path).unwrap();
+  let height = shades.len() / width;
+  let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+  encoder.set_color(png::ColorType::RGB);
+  encoder.set_depth(png::BitDepth::Eight);
+  let pixels: Vec<u8> = shades.iter().flat_map(|shade| SHADES[*shade as usize].iter().copied()).collect();
+  let mut writer = encoder.write_header().unwrap();
+  writer.write_image_data(&pixels).unwrap();
+}
@@ -0,0 +1,99 @@
+use std::str::FromStr;
+use crate::bus::Interrupt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+  Right = 1 << 0,
+  Left = 1 << 1,
+  Up = 1 << 2,
+  Down = 1 << 3,
+  A = 1 << 4,
+  B = 1 << 5,
+  Select = 1 << 6,
+  Start = 1 << 7,
+}
+
+impl FromStr for Button {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "right" => Ok(Button::Right),
+      "left" => Ok(Button::Left),
+      "up" => Ok(Button::Up),
+      "down" => Ok(Button::Down),
+      "a" => Ok(Button::A),
+      "b" => Ok(Button::B),
+      "select" => Ok(Button::Select),
+      "start" => Ok(Button::Start),
+      _ => Err(format!("Unknown button '{}'", s)),
+    }
+  }
+}
+
+// P1 (0xFF00). Buttons are read 4 at a time: bit 4 low selects the directions,
+// bit 5 low the action buttons, and pressed buttons read as 0.
+pub struct Joypad {
+  // Which buttons are held down, one bit per Button.
+  pressed: u8,
+  select: u8,
+  interrupts: u8,
+}
+
+impl Default for Joypad {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Joypad {
+  pub fn new() -> Self {
+    Joypad { pressed: 0, select: 0x30, interrupts: 0 }
+  }
+
+  pub fn read(&self) -> u8 {
+    0xC0 | self.select | self.lines()
+  }
+
+  pub fn write(&mut self, data: u8) {
+    self.update(|joypad| joypad.select = data & 0x30);
+  }
+
+  pub fn set(&mut self, button: Button, pressed: bool) {
+    self.update(|joypad| {
+      if pressed {
+        joypad.pressed |= button as u8;
+      } else {
+        joypad.pressed &= !(button as u8);
+      }
+    });
+  }
+
+  // Interrupts requested since the last call, as IF bits.
+  pub fn take_interrupts(&mut self) -> u8 {
+    let interrupts = self.interrupts;
+    self.interrupts = 0;
+    interrupts
+  }
+
+  // The interrupt fires when any of the 4 input lines goes from high to low,
+  // either from a button press or from selecting a group with a button held.
+  fn update<F: FnOnce(&mut Self)>(&mut self, f: F) {
+    let before = self.lines();
+    f(self);
+    if before & !self.lines() != 0 {
+      self.interrupts |= Interrupt::Joypad as u8;
+    }
+  }
+
+  fn lines(&self) -> u8 {
+    let mut lines = 0x0F;
+    if self.select & 0x10 == 0 {
+      lines &= !(self.pressed & 0x0F);
+    }
+    if self.select & 0x20 == 0 {
+      lines &= !(self.pressed >> 4);
+    }
+    lines
+  }
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use piston_window::Key;
+use gameboi::joypad::Button;
+
+// Keyboard bindings. Loaded from a file with one `<button> = <key>` per line,
+// keys named like piston's Key enum (Up, Z, Return, Backspace, LShift, NumPad8...):
+//
+//   # WASD instead of the arrows
+//   up = W
+//   left = A
+//   down = S
+//   right = D
+//
+// Buttons missing from the file keep their default key.
+pub struct Keymap {
+  bindings: Vec<(Key, Button)>,
+}
+
+impl Keymap {
+  pub fn new() -> Self {
+    Keymap {
+      bindings: vec![
+        (Key::Up, Button::Up),
+        (Key::Down, Button::Down),
+        (Key::Left, Button::Left),
+        (Key::Right, Button::Right),
+        (Key::Z, Button::A),
+        (Key::X, Button::B),
+        (Key::Return, Button::Start),
+        (Key::Backspace, Button::Select),
+      ],
+    }
+  }
+
+  // Defaults for the other GameBoys (1-3): the left of the keyboard, the right
+  // of it and the numpad.
+  pub fn player(player: usize) -> Self {
+    let bindings = match player {
+      1 => vec![
+        (Key::W, Button::Up),
+        (Key::S, Button::Down),
+        (Key::A, Button::Left),
+        (Key::D, Button::Right),
+        (Key::G, Button::A),
+        (Key::F, Button::B),
+        (Key::T, Button::Start),
+        (Key::R, Button::Select),
+      ],
+      2 => vec![
+        (Key::I, Button::Up),
+        (Key::K, Button::Down),
+        (Key::J, Button::Left),
+        (Key::L, Button::Right),
+        (Key::Period, Button::A),
+        (Key::Comma, Button::B),
+        (Key::O, Button::Start),
+        (Key::U, Button::Select),
+      ],
+      3 => vec![
+        (Key::NumPad8, Button::Up),
+        (Key::NumPad5, Button::Down),
+        (Key::NumPad4, Button::Left),
+        (Key::NumPad6, Button::Right),
+        (Key::NumPad3, Button::A),
+        (Key::NumPad1, Button::B),
+        (Key::NumPadEnter, Button::Start),
+        (Key::NumPadPlus, Button::Select),
+      ],
+      _ => return Keymap::new(),
+    };
+    Keymap { bindings }
+  }
+
+  // Override these bindings with the ones in a file.
+  pub fn load(self, path: &str) -> Self {
+    let mut keymap = self;
+    let config = fs::read_to_string(path).unwrap_or_else(|e| panic!("Can't read keymap {}: {}", path, e));
+    for (number, line) in config.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let (button, key) = match line.split_once('=') {
+        Some((button, key)) => (button.trim(), key.trim()),
+        None => panic!("Invalid keymap line {}: '{}'", number + 1, line),
+      };
+      let button: Button = button.parse().unwrap_or_else(|e: String| panic!("Invalid keymap line {}: {}", number + 1, e));
+      let key = parse_key(key).unwrap_or_else(|| panic!("Invalid keymap line {}: unknown key '{}'", number + 1, key));
+      keymap.bindings.retain(|(_, b)| *b != button);
+      keymap.bindings.push((key, button));
+    }
+    keymap
+  }
+
+  pub fn button(&self, key: Key) -> Option<Button> {
+    self.bindings.iter().find(|(k, _)| *k == key).map(|(_, b)| *b)
+  }
+}
+
+// Look the name up among every key piston knows about: the ASCII range and the
+// SDL scancode based ones above 0x40000000.
+fn parse_key(name: &str) -> Option<Key> {
+  (0x00..0x80).chain(0x40000039..0x40000120)
+    .map(Key::from)
+    .filter(|key| *key != Key::Unknown)
+    .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
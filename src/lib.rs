@@ -0,0 +1,158 @@
+use std::sync::mpsc::{Receiver, SyncSender, TryRecvError, TrySendError};
+
+pub mod cpu;
+pub mod bus;
+pub mod ppu;
+pub mod model;
+pub mod joypad;
+pub mod headless;
+pub mod image;
+pub mod path;
+pub mod pacer;
+pub mod timer;
+pub mod apu;
+pub mod audio;
+pub mod vgm;
+pub mod gbs;
+pub mod serial;
+pub mod link;
+pub mod lockstep;
+pub mod printer;
+pub mod adapter;
+use cpu::CPU;
+use bus::Bus;
+use model::Model;
+use pacer::{Pacer, Speed};
+use joypad::Button;
+use audio::{Audio, Recorder};
+use apu::Scope;
+
+// T-cycles in one frame: 154 lines of 456 dots.
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+pub struct Gameboy<'a> {
+ pub cpu: CPU<'a>,
+ game: Vec<u8>,
+ // Everything played also goes to a WAV file.
+ pub recorder: Option<Recorder>,
+}
+
+impl<'a> Gameboy<'a> {
+  pub fn new(model: Model, game: Vec<u8>) -> Self {
+    Gameboy { 
+      cpu: CPU::new(model, &game),
+      game,
+      recorder: None,
+    }
+  }
+
+  // Plug in the bus and copy the cartridge into it.
+  pub fn connect_bus(&mut self, bus: &'a mut Bus) {
+    self.cpu.connect_bus(bus);
+    for i in 0..=0x7FFF {
+      let b = self.game[i]; 
+      self.cpu.write(i as u16, b); 
+    }
+  }
+
+  // Run a single instruction (and any interrupt dispatch). Returns the T-cycles it took.
+  pub fn step(&mut self) -> u32 {
+    // FETCH, DEOCDE, EXECUTE. A halted CPU just idles until an interrupt shows up.
+    let mut duration = 1;
+    if !self.cpu.is_halted() {
+      let opcode = self.cpu.fetch();
+      let (op_duration, op) = self.cpu.decode(opcode);
+      op(&mut self.cpu);
+      duration = op_duration;
+    }
+    duration += self.cpu.interrupt();
+    // Durations are in machine cycles, the rest of the hardware runs on T-cycles.
+    self.cpu.finish(duration as u32 * 4)
+  }
+
+  // Run until the PPU finishes a frame. With the LCD off no frame ever finishes,
+  // so give up after a frame's worth of cycles.
+  pub fn run_frame(&mut self) {
+    let mut cycles = 0;
+    while cycles < CYCLES_PER_FRAME {
+      cycles += self.step();
+      if self.cpu.bus_mut().ppu.take_frame() {
+        break;
+      }
+    }
+  }
+
+  // Hand the samples of the last frame to `audio` and pick the rate for the next one.
+  pub fn play_audio(&mut self, audio: &mut dyn Audio) {
+    let apu = &mut self.cpu.bus_mut().apu;
+    let samples = apu.take_samples();
+    if let Some(recorder) = &mut self.recorder {
+      recorder.write(&samples, apu.take_stems());
+    }
+    audio.queue(&samples);
+    apu.set_sample_rate(audio.rate());
+  }
+
+  pub fn toggle_sound(&mut self, toggle: SoundToggle) {
+    let apu = &mut self.cpu.bus_mut().apu;
+    match toggle {
+      SoundToggle::Mute(channel) => apu.toggle_mute(channel),
+      SoundToggle::Solo(channel) => apu.toggle_solo(channel),
+      SoundToggle::Scope => {
+        let enabled = apu.scope_enabled();
+        apu.enable_scope(!enabled);
+      },
+    }
+  }
+
+  // Emulation loop for the windowed frontend, meant to run on its own thread.
+  // Finished frames go out on `frames`; if the window hasn't picked up the previous
+  // one yet it's dropped, so rendering never holds emulation back.
+  pub fn run(&mut self, inputs: Receiver<Input>, frames: SyncSender<Frame>, speed: Speed, audio: &mut dyn Audio) {
+    let mut pacer = Pacer::new(speed);
+    self.play_audio(audio);
+    loop {
+      loop {
+        match inputs.try_recv() {
+          Ok(Input::Speed(speed)) => pacer.set_speed(speed),
+          Ok(Input::Button(_, button, pressed)) => self.cpu.bus_mut().joypad.set(button, pressed),
+          Ok(Input::Sound(toggle)) => self.toggle_sound(toggle),
+          Err(TryRecvError::Empty) => break,
+          // Window closed.
+          Err(TryRecvError::Disconnected) => return,
+        }
+      }
+      self.run_frame();
+      self.play_audio(audio);
+      let frame = Frame {
+        screens: vec![self.cpu.bus().ppu.framebuffer().to_vec()],
+        scope: self.cpu.bus_mut().apu.take_scope(),
+      };
+      if let Err(TrySendError::Disconnected(_)) = frames.try_send(frame) {
+        return;
+      }
+      pacer.wait();
+    }
+  }
+}
+
+// Sound debugging keys.
+pub enum SoundToggle {
+  Mute(usize),
+  Solo(usize),
+  Scope,
+}
+
+// Sent from the window to the emulation thread.
+pub enum Input {
+  Speed(Speed),
+  // Which GameBoy (0 or 1 when two are linked), the button, pressed or released.
+  Button(usize, Button, bool),
+  Sound(SoundToggle),
+}
+
+// Sent from the emulation thread to the window: each GameBoy's screen.
+pub struct Frame {
+  pub screens: Vec<Vec<u8>>,
+  pub scope: Option<Scope>,
+}
@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvError};
+use std::thread;
+
+// How far (in T-cycles) either side may run ahead of the other. Transfers are
+// delivered this long after they start, so they always land in the other side's
+// future and happen at the same point of its emulation every run.
+const LATENCY: u64 = 4096;
+// Tell the other side how far we are at least this often.
+const SYNC_PERIOD: u64 = 1024;
+
+// The other end of the link cable. The side with the internal clock sends its
+// byte as the transfer starts and gets the other side's byte when it ends; the
+// other side sees the byte arrive and answers with its own.
+pub trait Link {
+  // We started a transfer at `cycle`, with `data` in SB.
+  fn send(&mut self, cycle: u64, data: u8);
+  // The byte shifted in by the transfer we started, now complete at `cycle`.
+  fn receive(&mut self, cycle: u64) -> u8;
+  // A transfer the other side clocked, due by `cycle`. It has to be answered with `reply`.
+  fn incoming(&mut self, cycle: u64) -> Option<u8>;
+  fn reply(&mut self, data: u8);
+  // Time passes: keep in step with the other side.
+  fn sync(&mut self, cycle: u64);
+}
+
+enum Message {
+  // The sender got this far.
+  Sync(u64),
+  // A transfer the sender clocked started then.
+  Byte(u64, u8),
+  // Answer to a Byte.
+  Reply(u64, u8),
+}
+
+impl Message {
+  fn encode(&self) -> [u8; 10] {
+    let (tag, cycle, data) = match *self {
+      Message::Sync(cycle) => (0, cycle, 0),
+      Message::Byte(cycle, data) => (1, cycle, data),
+      Message::Reply(cycle, data) => (2, cycle, data),
+    };
+    let mut bytes = [0; 10];
+    bytes[0] = tag;
+    bytes[1..9].copy_from_slice(&cycle.to_le_bytes());
+    bytes[9] = data;
+    bytes
+  }
+
+  fn decode(bytes: &[u8; 10]) -> Option<Self> {
+    let mut cycle = [0; 8];
+    cycle.copy_from_slice(&bytes[1..9]);
+    let cycle = u64::from_le_bytes(cycle);
+    match bytes[0] {
+      0 => Some(Message::Sync(cycle)),
+      1 => Some(Message::Byte(cycle, bytes[9])),
+      2 => Some(Message::Reply(cycle, bytes[9])),
+      _ => None,
+    }
+  }
+}
+
+// Link cable to another rustboy over TCP. A thread reads the socket so the
+// emulation only blocks when it's too far ahead, or waiting on an answer.
+pub struct TcpLink {
+  stream: TcpStream,
+  messages: Receiver<Message>,
+  // How far the other side is known to be.
+  peer_cycle: u64,
+  cycle: u64,
+  last_sync: u64,
+  // Bytes from the other side, with the cycle they're due at.
+  incoming: VecDeque<(u64, u8)>,
+  replies: VecDeque<u8>,
+  // The other side went away, carry on as if nothing was plugged in.
+  disconnected: bool,
+}
+
+impl TcpLink {
+  // Wait for the other GameBoy to connect.
+  pub fn host(port: u16) -> Self {
+    let listener = TcpListener::bind(("0.0.0.0", port)).unwrap();
+    eprintln!("Waiting for the link cable on port {}", port);
+    let (stream, address) = listener.accept().unwrap();
+    eprintln!("Link cable connected to {}", address);
+    TcpLink::new(stream)
+  }
+
+  pub fn connect(address: &str) -> Self {
+    let stream = TcpStream::connect(address).unwrap();
+    eprintln!("Link cable connected to {}", address);
+    TcpLink::new(stream)
+  }
+
+  fn new(stream: TcpStream) -> Self {
+    stream.set_nodelay(true).unwrap();
+    let mut reader = stream.try_clone().unwrap();
+    let (tx, messages) = mpsc::channel();
+    thread::spawn(move || {
+      let mut bytes = [0; 10];
+      while reader.read_exact(&mut bytes).is_ok() {
+        let message = match Message::decode(&bytes) {
+          Some(message) => message,
+          None => return,
+        };
+        if tx.send(message).is_err() {
+          return;
+        }
+      }
+    });
+    TcpLink {
+      stream,
+      messages,
+      peer_cycle: 0,
+      cycle: 0,
+      last_sync: 0,
+      incoming: VecDeque::new(),
+      replies: VecDeque::new(),
+      disconnected: false,
+    }
+  }
+
+  fn write(&mut self, message: Message) {
+    if self.disconnected {
+      return;
+    }
+    if self.stream.write_all(&message.encode()).is_err() {
+      self.disconnect();
+    }
+  }
+
+  fn disconnect(&mut self) {
+    if !self.disconnected {
+      eprintln!("Link cable disconnected");
+    }
+    self.disconnected = true;
+  }
+
+  fn handle(&mut self, message: Message) {
+    match message {
+      Message::Sync(cycle) => self.peer_cycle = cycle,
+      Message::Byte(cycle, data) => {
+        self.peer_cycle = cycle;
+        self.incoming.push_back((cycle + LATENCY, data));
+      },
+      Message::Reply(cycle, data) => {
+        self.peer_cycle = cycle;
+        self.replies.push_back(data);
+      },
+    }
+  }
+
+  fn poll(&mut self) {
+    while let Ok(message) = self.messages.try_recv() {
+      self.handle(message);
+    }
+  }
+
+  // Block until the other side says something.
+  fn wait(&mut self) {
+    // It may be waiting on us too.
+    self.send_sync();
+    match self.messages.recv() {
+      Ok(message) => self.handle(message),
+      Err(RecvError) => self.disconnect(),
+    }
+  }
+
+  fn send_sync(&mut self) {
+    if self.last_sync != self.cycle {
+      self.last_sync = self.cycle;
+      self.write(Message::Sync(self.cycle));
+    }
+  }
+}
+
+impl Link for TcpLink {
+  fn send(&mut self, cycle: u64, data: u8) {
+    self.cycle = cycle;
+    self.write(Message::Byte(cycle, data));
+  }
+
+  fn receive(&mut self, cycle: u64) -> u8 {
+    self.cycle = cycle;
+    loop {
+      self.poll();
+      if let Some(data) = self.replies.pop_front() {
+        return data;
+      }
+      if self.disconnected {
+        return 0xFF;
+      }
+      // Both sides started a transfer at once. Nobody is listening on this
+      // side, but the other one still needs an answer.
+      while self.incoming.pop_front().is_some() {
+        self.write(Message::Reply(cycle, 0xFF));
+      }
+      self.wait();
+    }
+  }
+
+  fn incoming(&mut self, cycle: u64) -> Option<u8> {
+    match self.incoming.front() {
+      Some(&(due, data)) if due <= cycle => {
+        self.incoming.pop_front();
+        Some(data)
+      },
+      _ => None,
+    }
+  }
+
+  fn reply(&mut self, data: u8) {
+    self.write(Message::Reply(self.cycle, data));
+  }
+
+  fn sync(&mut self, cycle: u64) {
+    self.cycle = cycle;
+    if cycle - self.last_sync >= SYNC_PERIOD {
+      self.send_sync();
+    }
+    self.poll();
+    while !self.disconnected && cycle >= self.peer_cycle + LATENCY {
+      self.wait();
+    }
+  }
+}
+
+// What's on its way to one end of a LocalLink.
+#[derive(Default)]
+struct Port {
+  incoming: VecDeque<(u64, u8)>,
+  replies: VecDeque<u8>,
+}
+
+// Link cable between two GameBoys in the same process. They have to be stepped
+// in lockstep (see lockstep.rs), so bytes are simply handed over in memory.
+pub struct LocalLink {
+  ports: Rc<RefCell<[Port; 2]>>,
+  side: usize,
+}
+
+impl LocalLink {
+  // Both ends of a new cable.
+  pub fn pair() -> (Self, Self) {
+    let ports = Rc::new(RefCell::new([Port::default(), Port::default()]));
+    (LocalLink { ports: ports.clone(), side: 0 }, LocalLink { ports, side: 1 })
+  }
+
+  fn other(&self) -> usize {
+    1 - self.side
+  }
+}
+
+impl Link for LocalLink {
+  fn send(&mut self, cycle: u64, data: u8) {
+    self.ports.borrow_mut()[self.other()].incoming.push_back((cycle, data));
+  }
+
+  // The other side is never more than an instruction behind, it has answered by now.
+  fn receive(&mut self, _cycle: u64) -> u8 {
+    self.ports.borrow_mut()[self.side].replies.pop_front().unwrap_or(0xFF)
+  }
+
+  fn incoming(&mut self, cycle: u64) -> Option<u8> {
+    let mut ports = self.ports.borrow_mut();
+    let incoming = &mut ports[self.side].incoming;
+    match incoming.front() {
+      Some(&(due, data)) if due <= cycle => {
+        incoming.pop_front();
+        Some(data)
+      },
+      _ => None,
+    }
+  }
+
+  fn reply(&mut self, data: u8) {
+    self.ports.borrow_mut()[self.other()].replies.push_back(data);
+  }
+
+  fn sync(&mut self, _cycle: u64) {}
+}
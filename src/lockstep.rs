@@ -0,0 +1,58 @@
+use std::sync::mpsc::{Receiver, SyncSender, TryRecvError, TrySendError};
+use crate::{Gameboy, Input, Frame, CYCLES_PER_FRAME};
+use crate::audio::Audio;
+use crate::pacer::{Pacer, Speed};
+
+// Run linked GameBoys until each finishes a frame (or a frame's worth of cycles
+// passes with the LCD off). Whichever is furthest behind runs the next instruction,
+// so they never drift more than an instruction apart and every run plays out the
+// same. Returns every frame.
+pub fn run_frame(gbs: &mut [Gameboy]) -> Vec<Vec<u8>> {
+  let start: Vec<u64> = gbs.iter().map(|gb| gb.cpu.bus().cycles()).collect();
+  let mut frames: Vec<Option<Vec<u8>>> = vec![None; gbs.len()];
+  loop {
+    let done = |i: usize, gb: &Gameboy| frames[i].is_some() || gb.cpu.bus().cycles() - start[i] >= CYCLES_PER_FRAME as u64;
+    if gbs.iter().enumerate().all(|(i, gb)| done(i, gb)) {
+      break;
+    }
+    let behind = (0..gbs.len()).min_by_key(|&i| gbs[i].cpu.bus().cycles()).unwrap();
+    step(&mut gbs[behind], &mut frames[behind]);
+  }
+  frames.into_iter().zip(gbs.iter())
+    .map(|(frame, gb)| frame.unwrap_or_else(|| gb.cpu.bus().ppu.framebuffer().to_vec()))
+    .collect()
+}
+
+// Run one instruction, keeping the first frame that finishes.
+fn step(gb: &mut Gameboy, frame: &mut Option<Vec<u8>>) {
+  gb.step();
+  if gb.cpu.bus_mut().ppu.take_frame() && frame.is_none() {
+    *frame = Some(gb.cpu.bus().ppu.framebuffer().to_vec());
+  }
+}
+
+// Emulation loop for linked GameBoys in one window, see Gameboy::run.
+// Only the first one is heard.
+pub fn run(gbs: &mut [Gameboy], inputs: Receiver<Input>, frames: SyncSender<Frame>, speed: Speed, audio: &mut dyn Audio) {
+  let mut pacer = Pacer::new(speed);
+  gbs[0].play_audio(audio);
+  loop {
+    loop {
+      match inputs.try_recv() {
+        Ok(Input::Speed(speed)) => pacer.set_speed(speed),
+        Ok(Input::Button(player, button, pressed)) => gbs[player].cpu.bus_mut().joypad.set(button, pressed),
+        Ok(Input::Sound(toggle)) => gbs[0].toggle_sound(toggle),
+        Err(TryRecvError::Empty) => break,
+        // Window closed.
+        Err(TryRecvError::Disconnected) => return,
+      }
+    }
+    let screens = run_frame(gbs);
+    gbs[0].play_audio(audio);
+    let frame = Frame { screens, scope: gbs[0].cpu.bus_mut().apu.take_scope() };
+    if let Err(TrySendError::Disconnected(_)) = frames.try_send(frame) {
+      return;
+    }
+    pacer.wait();
+  }
+}
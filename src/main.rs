@@ -1,82 +1,238 @@
-#[allow(dead_code)]
-use std::env::args;
-use std::io::prelude::*;
-use std::fs::File;
-use std::{thread, time};
+use std::io::{self, prelude::*};
+use std::fs::{self, File};
+use std::sync::mpsc;
+use std::thread;
 
 mod display;
-mod cpu;
-mod bus;
+mod args;
+mod keymap;
 use display::Display;
-use cpu::CPU;
-use bus::Bus;
+use args::Args;
+use keymap::Keymap;
+use gameboi::{Gameboy, Input, lockstep, headless, audio};
+use gameboi::bus::Bus;
+use gameboi::model::Model;
+use gameboi::audio::{NullAudio, Recorder};
+use gameboi::gbs::{self, Gbs, Player};
+use gameboi::link::{TcpLink, LocalLink};
+use gameboi::printer::Printer;
+use gameboi::adapter::AdapterPort;
 
-struct Gameboy<'a> {
- cpu: CPU<'a>,
- display: Display,
- game: Vec<u8>,
-}
+// Checks the cartridge header and returns the game's title.
+fn boot_game(game: &[u8]) -> String {
+  // Compare NINTENDO LOGO to logo range in the ROM header. If they're not the same, panic.
+  if &game[0x104..0x134] == NINTENDO_LOGO {
 
-impl Gameboy<'_> {
-  fn new() -> Self {
-    Gameboy { 
-      cpu: CPU::new(),
-      display: Display::new(),
-      game: Vec::new(),
+    // Check range 0x104-0x133 and compute checksum by adding all bytes together
+    // + 25 in decimal. If the least significant bit is NOT 0, panic.
+    let mut sum: u16 = 25;
+    for byte in &game[0x134..0x14d] {
+      sum += *byte as u16; 
+    }
+    if sum & 1 != 0 {
+      panic!("Invalid checksum");
     }
+  } else {
+    panic!("Invalid Nintento logo");
   }
 
-  fn boot_game(&mut self) {
-    // Compare NINTENDO LOGO to logo range in the ROM header. If they're not the same, panic.
-    if &self.game[0x104..0x134] == NINTENDO_LOGO {
+  String::from_utf8(game[0x134..0x144].to_vec()).unwrap()
+}
+
+// Run on the requested hardware, or whatever the cartridge header asks for.
+fn cartridge_models(args: &Args, games: &[Vec<u8>]) -> Vec<Model> {
+  games.iter().map(|game| args.model.unwrap_or_else(|| Model::from_header(game))).collect()
+}
 
-      // Check range 0x104-0x133 and compute checksum by adding all bytes together
-      // + 25 in decimal. If the least significant bit is NOT 0, panic.
-      let mut sum: u16 = 25;
-      for byte in &self.game[0x134..0x14d] {
-        sum += *byte as u16; 
+// Build a GameBoy for each game and hand them to `f`. Each CPU borrows its bus, so
+// they all have to live here.
+fn with_plain_gameboys<F: FnOnce(&mut [Gameboy])>(args: &Args, models: Vec<Model>, games: Vec<Vec<u8>>, f: F) {
+  let mut buses: Vec<Bus> = models.iter().map(|&model| {
+    let mut bus = Bus::new(model);
+    bus.ppu.set_renderer(args.renderer);
+    bus
+  }).collect();
+  let mut gbs: Vec<Gameboy> = buses.iter_mut().zip(games).zip(models).map(|((bus, game), model)| {
+    let mut gb = Gameboy::new(model, game);
+    gb.connect_bus(bus);
+    gb
+  }).collect();
+  f(&mut gbs);
+}
+
+// Same, plus whatever the command line plugs into the first one: link cable,
+// serial capture, recordings.
+fn with_gameboys<F: FnOnce(&mut [Gameboy])>(args: &Args, models: Vec<Model>, games: Vec<Vec<u8>>, f: F) {
+  with_plain_gameboys(args, models, games, |gbs| {
+    let gb = &mut gbs[0];
+    if let Some(port) = args.link_host {
+      gb.cpu.bus_mut().serial.connect(Box::new(TcpLink::host(port)));
+    } else if let Some(address) = &args.link_connect {
+      gb.cpu.bus_mut().serial.connect(Box::new(TcpLink::connect(address)));
+    } else if let Some(path) = &args.printer {
+      gb.cpu.bus_mut().serial.connect(Box::new(Printer::new(path)));
+    }
+    if let Some(path) = &args.serial_out {
+      let capture: Box<dyn Write> = match path.as_str() {
+        "-" => Box::new(io::stdout()),
+        path => Box::new(File::create(path).unwrap()),
+      };
+      gb.cpu.bus_mut().serial.set_capture(capture);
+    }
+    if let Some(path) = &args.record_audio {
+      let recorder = Recorder::create(path, args.record_stems, args.sample_rate);
+      if recorder.has_stems() {
+        gb.cpu.bus_mut().apu.enable_stems();
       }
-      if sum % 2 != 0 {
-        panic!("Invalid checksum");
+      gb.recorder = Some(recorder);
+    }
+    if let Some(path) = &args.record_vgm {
+      gb.cpu.bus_mut().record_vgm(path);
+    }
+    f(gbs);
+    gbs[0].cpu.bus_mut().finish_vgm();
+  });
+}
+
+fn with_gameboy<F: FnOnce(&mut Gameboy)>(args: &Args, model: Model, game: Vec<u8>, f: F) {
+  with_gameboys(args, vec![model], vec![game], |gbs| f(&mut gbs[0]));
+}
+
+// GameBoys joined by a link cable, or all plugged into a Four Player Adapter.
+// Everything on the command line applies to the first one, the others just run their game.
+fn with_linked_gameboys<F: FnOnce(&mut [Gameboy])>(args: &Args, models: Vec<Model>, games: Vec<Vec<u8>>, f: F) {
+  with_gameboys(args, models, games, |gbs| {
+    if args.four_player.is_some() {
+      let ports = AdapterPort::ports(gbs.len());
+      for (gb, port) in gbs.iter_mut().zip(ports) {
+        gb.cpu.bus_mut().serial.connect(Box::new(port));
       }
     } else {
-      panic!("Invalid Nintento logo");
+      let (link, link2) = LocalLink::pair();
+      gbs[0].cpu.bus_mut().serial.connect(Box::new(link));
+      gbs[1].cpu.bus_mut().serial.connect(Box::new(link2));
     }
+    f(gbs);
+  });
+}
 
-    // Since it's a valid game, update the window's name to the Game's name
-    let game_title = &String::from_utf8(self.game[0x134..0x144].to_vec()).unwrap();
-    self.display.set_title(game_title);
+// GBS files have no cartridge or screen, songs are picked from the terminal.
+fn play_gbs(args: &Args, gbs: Gbs) {
+  println!("{} - {} ({})", gbs.title, gbs.author, gbs.copyright);
+  // The synthesized ROM has no real header to pick a model from.
+  let model = args.model.unwrap_or(Model::DMG);
+  let rom = gbs.rom();
+  with_gameboy(args, model, rom, |gb| {
+    let mut player = Player::new(gb, gbs, args.song);
+    gbs::print_song(&player);
+    if let Some(options) = &args.headless {
+      gbs::run_headless(gb, &mut player, options.frames, &mut NullAudio::new(args.sample_rate));
+      return;
+    }
 
+    println!("n: next song, p: previous song, <number>: that song, m<1-4>: mute a channel, s<1-4>: solo it, q: quit");
+    let (command_tx, command_rx) = mpsc::channel();
+    thread::spawn(move || {
+      for line in io::stdin().lock().lines() {
+        if let Some(command) = gbs::parse_command(&line.unwrap()) {
+          if command_tx.send(command).is_err() {
+            return;
+          }
+        }
+      }
+    });
+    let mut audio = audio::open(args.sample_rate);
+    gbs::run(gb, &mut player, command_rx, audio.as_mut());
+  });
+}
+
+fn main() {
+  let args = Args::parse();
+  let mut game = Vec::new();
+  let mut f = File::open(&args.rom).unwrap();
+  f.read_to_end(&mut game).unwrap();
+  if Gbs::is_gbs(&game) {
+    play_gbs(&args, Gbs::parse(&game));
+    return;
   }
+  let title = boot_game(&game);
+  // The other GameBoys play the same game unless told otherwise.
+  let players = args.four_player.unwrap_or(if args.link_local { 2 } else { 1 });
+  let mut games = vec![game];
+  for player in 1..players {
+    games.push(match args.link_roms.get(player - 1) {
+      Some(path) => {
+        let game = fs::read(path).unwrap();
+        boot_game(&game);
+        game
+      },
+      None => games[0].clone(),
+    });
+  }
+
+  let models = cartridge_models(&args, &games);
 
-  fn start(&mut self) {
-    while let Some(e) = self.display.poll() {
-      // FETCH, DEOCDE, EXECUTE
-      let opcode = self.cpu.fetch();
-      let (duration, op) = self.cpu.decode(opcode);
-      println!("current op: {:x}, duration: {}", opcode, duration);
-      op(&mut self.cpu);
-   		self.display.refresh(&e); 
-      thread::sleep(time::Duration::from_millis(500));
+  if let Some(options) = &args.headless {
+    let mut audio = NullAudio::new(args.sample_rate);
+    if players > 1 {
+      with_linked_gameboys(&args, models, games, |gbs| headless::run_linked(gbs, options, &mut audio));
+    } else {
+      with_gameboys(&args, models, games, |gbs| headless::run(&mut gbs[0], options, &mut audio));
     }
+    return;
   }
-}
 
-fn main() {
-  let mut gb = Gameboy::new();
-  let file_name = args().nth(1).unwrap();
-  let mut f = File::open(&file_name).unwrap();
-  f.read_to_end(&mut gb.game).unwrap();
-
-  let mut bus = Bus::new();
-  gb.cpu.connect_bus(&mut bus);
-  for i in 0..=0x7FFF {
-    let b = gb.game[i]; 
-    gb.cpu.write(i as u16, b); 
+  // Emulation gets its own thread, the window only shows frames and forwards input.
+  let (input_tx, input_rx) = mpsc::channel();
+  let (frame_tx, frame_rx) = mpsc::sync_channel(1);
+  let speed = args.speed;
+  let scale = args.scale;
+  let mut keymaps = vec![match &args.keymap {
+    Some(path) => Keymap::new().load(path),
+    None => Keymap::new(),
+  }];
+  for player in 1..players {
+    keymaps.push(match &args.player_keymaps[player - 1] {
+      Some(path) => Keymap::player(player).load(path),
+      None => Keymap::player(player),
+    });
   }
-  gb.boot_game();
+  let emulation = thread::spawn(move || {
+    // SDL wants its audio device opened on the thread that uses it.
+    let mut audio = audio::open(args.sample_rate);
+    if players > 1 {
+      with_linked_gameboys(&args, models, games, |gbs| lockstep::run(gbs, input_rx, frame_tx, speed, audio.as_mut()));
+    } else {
+      with_gameboys(&args, models, games, |gbs| gbs[0].run(input_rx, frame_tx, speed, audio.as_mut()));
+    }
+  });
 
-  gb.start();
+  let mut display = Display::new(scale, keymaps.len());
+  display.set_title(&title);
+  while let Some(e) = display.poll() {
+    let mut inputs = Vec::new();
+    if let Some(speed) = display.speed_change(&e) {
+      inputs.push(Input::Speed(speed));
+    }
+    for (player, keymap) in keymaps.iter().enumerate() {
+      if let Some((button, pressed)) = display.button_change(&e, keymap) {
+        inputs.push(Input::Button(player, button, pressed));
+      }
+    }
+    if let Some(toggle) = display.sound_change(&e) {
+      inputs.push(Input::Sound(toggle));
+    }
+    // The emulation thread is gone, most likely it panicked: the join below reports it.
+    if inputs.into_iter().any(|input| input_tx.send(input).is_err()) {
+      break;
+    }
+    if let Ok(frame) = frame_rx.try_recv() {
+      display.update(&frame.screens, frame.scope);
+    }
+    display.refresh(&e);
+  }
+  drop(input_tx);
+  emulation.join().unwrap();
 }
 
 const NINTENDO_LOGO: &[u8] = &[206, 237, 102, 102, 204, 13, 0, 11, 3, 115, 0, 131, 0, 12, 0, 13, 0, 8, 17, 31, 136, 137, 0, 14, 220, 204, 110, 230, 221, 221, 217, 153, 187, 187, 103, 99, 110, 14, 236, 204, 221, 220, 153, 159, 187, 185, 51, 62];
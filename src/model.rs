@@ -0,0 +1,133 @@
+use std::str::FromStr;
+
+// Hardware revisions. They all run the same CPU but the boot ROM leaves
+// different values behind, and a few peripherals have model-specific quirks.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+  DMG0, // Early original GameBoy
+  DMG,  // Original GameBoy
+  MGB,  // GameBoy Pocket
+  SGB,  // Super GameBoy
+  SGB2, // Super GameBoy 2
+  CGB,  // GameBoy Color
+  AGB,  // GameBoy Advance (running GB/GBC games)
+}
+
+impl Model {
+  // Pick the model a game asks for in its header. CGB flag at 0x143, SGB flag at 0x146
+  // (only honoured when the old licensee code at 0x14B is 0x33).
+  pub fn from_header(game: &[u8]) -> Self {
+    if game[0x143] & 0x80 != 0 {
+      Model::CGB
+    } else if game[0x146] == 0x03 && game[0x14B] == 0x33 {
+      Model::SGB
+    } else {
+      Model::DMG
+    }
+  }
+
+  pub fn is_cgb(&self) -> bool {
+    matches!(self, Model::CGB | Model::AGB)
+  }
+
+  pub fn is_sgb(&self) -> bool {
+    matches!(self, Model::SGB | Model::SGB2)
+  }
+
+  // Registers as the boot ROM leaves them when jumping to 0x100, laid out like
+  // the CPU stores them: ([A, F], [B, C, D, E, H, L]).
+  pub fn boot_registers(&self, game: &[u8]) -> ([u8; 2], [u8; 6]) {
+    // DMG/MGB boot ROMs leave H and C set unless the header checksum is 0.
+    let hc = if game[0x14D] == 0 { 0x00 } else { 0x30 };
+    let cgb_game = game[0x143] & 0x80 != 0;
+    match self {
+      Model::DMG0 => ([0x01, 0x00], [0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03]),
+      Model::DMG => ([0x01, 0x80 | hc], [0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D]),
+      Model::MGB => ([0xFF, 0x80 | hc], [0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D]),
+      Model::SGB => ([0x01, 0x00], [0x00, 0x14, 0x00, 0x00, 0xC0, 0x60]),
+      Model::SGB2 => ([0xFF, 0x00], [0x00, 0x14, 0x00, 0x00, 0xC0, 0x60]),
+      Model::CGB if cgb_game => ([0x11, 0x80], [0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]),
+      Model::CGB => ([0x11, 0x80], [0x00, 0x00, 0x00, 0x08, 0x00, 0x7C]),
+      // The AGB boot ROM runs an extra INC B, which also clears Z.
+      Model::AGB if cgb_game => ([0x11, 0x00], [0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D]),
+      Model::AGB => ([0x11, 0x00], [0x01, 0x00, 0x00, 0x08, 0x00, 0x7C]),
+    }
+  }
+
+  // I/O registers as the boot ROM leaves them.
+  pub fn io_registers(&self) -> Vec<(u16, u8)> {
+    let cgb = self.is_cgb();
+    let mut regs = vec![
+      (0xFF00, 0xCF), // P1
+      (0xFF01, 0x00), // SB
+      (0xFF02, if cgb { 0x7F } else { 0x7E }), // SC
+      (0xFF04, match self { Model::DMG0 => 0x18, Model::DMG | Model::MGB => 0xAB, _ => 0x00 }), // DIV
+      (0xFF05, 0x00), // TIMA
+      (0xFF06, 0x00), // TMA
+      (0xFF07, 0xF8), // TAC
+      (0xFF0F, 0xE1), // IF
+      (0xFF10, 0x80), // NR10
+      (0xFF11, 0xBF), // NR11
+      (0xFF12, 0xF3), // NR12
+      (0xFF13, 0xFF), // NR13
+      (0xFF14, 0xBF), // NR14
+      (0xFF16, 0x3F), // NR21
+      (0xFF17, 0x00), // NR22
+      (0xFF18, 0xFF), // NR23
+      (0xFF19, 0xBF), // NR24
+      (0xFF1A, 0x7F), // NR30
+      (0xFF1B, 0xFF), // NR31
+      (0xFF1C, 0x9F), // NR32
+      (0xFF1D, 0xFF), // NR33
+      (0xFF1E, 0xBF), // NR34
+      (0xFF20, 0xFF), // NR41
+      (0xFF21, 0x00), // NR42
+      (0xFF22, 0x00), // NR43
+      (0xFF23, 0xBF), // NR44
+      (0xFF24, 0x77), // NR50
+      (0xFF25, 0xF3), // NR51
+      (0xFF26, if self.is_sgb() { 0xF0 } else { 0xF1 }), // NR52
+      (0xFF40, 0x91), // LCDC
+      (0xFF41, if *self == Model::DMG0 { 0x81 } else { 0x85 }), // STAT
+      (0xFF42, 0x00), // SCY
+      (0xFF43, 0x00), // SCX
+      (0xFF44, 0x00), // LY
+      (0xFF45, 0x00), // LYC
+      (0xFF46, if cgb { 0x00 } else { 0xFF }), // DMA
+      (0xFF47, 0xFC), // BGP
+      (0xFF48, 0xFF), // OBP0
+      (0xFF49, 0xFF), // OBP1
+      (0xFF4A, 0x00), // WY
+      (0xFF4B, 0x00), // WX
+      (0xFFFF, 0x00), // IE
+    ];
+    // CGB-only registers read back as 0xFF on the other models.
+    if cgb {
+      regs.extend(&[(0xFF4D, 0x7E), (0xFF4F, 0xFE), (0xFF70, 0xF8)]);
+    } else {
+      regs.extend(&[(0xFF4D, 0xFF), (0xFF4F, 0xFF), (0xFF70, 0xFF)]);
+    }
+    for addr in 0xFF51..=0xFF55 {
+      regs.push((addr, 0xFF)); // HDMA1-5
+    }
+    regs
+  }
+}
+
+impl FromStr for Model {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "dmg0" => Ok(Model::DMG0),
+      "dmg" => Ok(Model::DMG),
+      "mgb" => Ok(Model::MGB),
+      "sgb" => Ok(Model::SGB),
+      "sgb2" => Ok(Model::SGB2),
+      "cgb" => Ok(Model::CGB),
+      "agb" => Ok(Model::AGB),
+      _ => Err(format!("Unknown model '{}'", s)),
+    }
+  }
+}
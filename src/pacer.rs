@@ -0,0 +1,71 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The GameBoy runs 4194304 T-cycles per second, 70224 per frame.
+pub const FRAME_RATE: f64 = 4194304.0 / 70224.0;
+
+// How much faster than real hardware fast-forward runs, and how much slower slow-motion does.
+const FAST_FORWARD: f64 = 4.0;
+const SLOW_MOTION: f64 = 0.5;
+
+// Give up catching up when this far behind (window dragged, debugger...), instead
+// of running a burst of frames as fast as possible.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+  Normal,
+  FastForward,
+  SlowMotion,
+  Uncapped,
+}
+
+impl FromStr for Speed {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "normal" => Ok(Speed::Normal),
+      "fast" => Ok(Speed::FastForward),
+      "slow" => Ok(Speed::SlowMotion),
+      "uncapped" => Ok(Speed::Uncapped),
+      _ => Err(format!("Unknown speed '{}'", s)),
+    }
+  }
+}
+
+// Keeps emulated frames in step with the host clock.
+pub struct Pacer {
+  speed: Speed,
+  next_frame: Instant,
+}
+
+impl Pacer {
+  pub fn new(speed: Speed) -> Self {
+    Pacer { speed, next_frame: Instant::now() }
+  }
+
+  pub fn set_speed(&mut self, speed: Speed) {
+    self.speed = speed;
+    self.next_frame = Instant::now();
+  }
+
+  // Block until the next frame is due. Frames are scheduled on absolute times so
+  // sleep inaccuracies don't add up.
+  pub fn wait(&mut self) {
+    let rate = match self.speed {
+      Speed::Normal => FRAME_RATE,
+      Speed::FastForward => FRAME_RATE * FAST_FORWARD,
+      Speed::SlowMotion => FRAME_RATE * SLOW_MOTION,
+      Speed::Uncapped => return,
+    };
+    self.next_frame += Duration::from_secs_f64(1.0 / rate);
+    let now = Instant::now();
+    if self.next_frame > now {
+      thread::sleep(self.next_frame - now);
+    } else if now - self.next_frame > MAX_LAG {
+      self.next_frame = now;
+    }
+  }
+}
@@ -0,0 +1,12 @@
+use std::path::Path;
+
+// out.png -> out-<suffix>.png
+pub fn suffixed_path(path: &str, suffix: &str) -> String {
+  let path = Path::new(path);
+  let stem = path.file_stem().unwrap().to_string_lossy();
+  let name = match path.extension() {
+    Some(ext) => format!("{}-{}.{}", stem, suffix, ext.to_string_lossy()),
+    None => format!("{}-{}", stem, suffix),
+  };
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
@@ -0,0 +1,562 @@
+use std::str::FromStr;
+use crate::bus::Interrupt;
+use crate::model::Model;
+
+mod fifo;
+use fifo::Fifo;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+// RGB for each of the 4 DMG shades, lightest first.
+pub const SHADES: [[u8; 3]; 4] = [[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]];
+
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+
+// LCDC bits
+const LCDC_BG_ENABLE: u8 = 1 << 0;
+const LCDC_OBJ_ENABLE: u8 = 1 << 1;
+const LCDC_OBJ_SIZE: u8 = 1 << 2;
+const LCDC_BG_MAP: u8 = 1 << 3;
+const LCDC_TILE_DATA: u8 = 1 << 4;
+const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
+const LCDC_WINDOW_MAP: u8 = 1 << 6;
+const LCDC_LCD_ENABLE: u8 = 1 << 7;
+
+// STAT bits
+const STAT_COINCIDENCE: u8 = 1 << 2;
+const STAT_HBLANK_INT: u8 = 1 << 3;
+const STAT_VBLANK_INT: u8 = 1 << 4;
+const STAT_OAM_INT: u8 = 1 << 5;
+const STAT_LYC_INT: u8 = 1 << 6;
+
+// OAM attribute bits
+const OBJ_PALETTE: u8 = 1 << 4;
+const OBJ_X_FLIP: u8 = 1 << 5;
+const OBJ_Y_FLIP: u8 = 1 << 6;
+const OBJ_BG_PRIORITY: u8 = 1 << 7;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy)]
+struct Sprite {
+  y: u8,
+  x: u8,
+  tile: u8,
+  flags: u8,
+}
+
+// How mode 3 turns VRAM into pixels.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Renderer {
+  // Whole line at once at the end of mode 3. Fast, fixed 172 dot mode 3.
+  Scanline,
+  // Dot by dot pixel FIFO. Slower, but mid-line register writes and mode 3
+  // timing behave like hardware.
+  Fifo,
+}
+
+impl FromStr for Renderer {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "scanline" => Ok(Renderer::Scanline),
+      "fifo" => Ok(Renderer::Fifo),
+      _ => Err(format!("Unknown renderer '{}'", s)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+  HBlank = 0,
+  VBlank = 1,
+  OamScan = 2,
+  Drawing = 3,
+}
+
+pub struct Ppu {
+  model: Model,
+  renderer: Renderer,
+  fifo: Fifo,
+  vram: [u8; 0x2000],
+  oam: [u8; 0xA0],
+
+  // Registers
+  lcdc: u8,
+  stat: u8,
+  scy: u8,
+  scx: u8,
+  ly: u8,
+  lyc: u8,
+  bgp: u8,
+  obp0: u8,
+  obp1: u8,
+  wy: u8,
+  wx: u8,
+
+  mode: Mode,
+  // Internal line counter. Differs from LY on line 153, where LY reads 0 early.
+  line: u8,
+  // STAT interrupt line. All sources are ORed together and the interrupt is only
+  // requested on a rising edge, so overlapping sources block each other.
+  stat_line: bool,
+  interrupts: u8,
+  // Sprites picked during OAM scan for the current line, in priority order.
+  line_sprites: Vec<Sprite>,
+
+  // Which window line is drawn next. Only advances on lines where the window
+  // was actually visible, so toggling it mid-frame resumes where it left off.
+  window_line: u8,
+  dots: u32,
+
+  // Shades (0 = white .. 3 = black) after palette lookup.
+  framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+  frame_ready: bool,
+}
+
+impl Ppu {
+  pub fn new(model: Model) -> Self {
+    Ppu {
+      model,
+      renderer: Renderer::Scanline,
+      fifo: Fifo::new(),
+      vram: [0; 0x2000],
+      oam: [0; 0xA0],
+      lcdc: 0,
+      stat: 0,
+      scy: 0,
+      scx: 0,
+      ly: 0,
+      lyc: 0,
+      bgp: 0,
+      obp0: 0,
+      obp1: 0,
+      wy: 0,
+      wx: 0,
+      mode: Mode::OamScan,
+      line: 0,
+      stat_line: false,
+      interrupts: 0,
+      line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+      window_line: 0,
+      dots: 0,
+      framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+      frame_ready: false,
+    }
+  }
+
+  pub fn set_renderer(&mut self, renderer: Renderer) {
+    self.renderer = renderer;
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match addr {
+      0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize],
+      0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
+      0xFF40 => self.lcdc,
+      0xFF41 => self.stat_register(),
+      0xFF42 => self.scy,
+      0xFF43 => self.scx,
+      0xFF44 => self.ly,
+      0xFF45 => self.lyc,
+      0xFF47 => self.bgp,
+      0xFF48 => self.obp0,
+      0xFF49 => self.obp1,
+      0xFF4A => self.wy,
+      0xFF4B => self.wx,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize] = data,
+      0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize] = data,
+      0xFF40 => {
+        // Turning the LCD off resets the line; it starts from the top when turned back on.
+        if data & LCDC_LCD_ENABLE == 0 {
+          self.ly = 0;
+          self.line = 0;
+          self.dots = 0;
+          self.window_line = 0;
+          self.mode = Mode::HBlank;
+          self.stat_line = false;
+        } else if self.lcdc & LCDC_LCD_ENABLE == 0 {
+          self.mode = Mode::OamScan;
+        }
+        self.lcdc = data;
+        self.update_stat();
+      },
+      0xFF41 => {
+        // DMG bug: writing STAT briefly enables every source, which fires a spurious
+        // interrupt during HBlank, VBlank or LY=LYC.
+        if !self.model.is_cgb() && self.lcd_enabled() {
+          self.stat = 0x78;
+          self.update_stat();
+        }
+        self.stat = data & 0x78;
+        self.update_stat();
+      },
+      0xFF42 => self.scy = data,
+      0xFF43 => self.scx = data,
+      0xFF44 => {}, // LY is read only
+      0xFF45 => {
+        self.lyc = data;
+        self.update_stat();
+      },
+      0xFF47 => self.bgp = data,
+      0xFF48 => self.obp0 = data,
+      0xFF49 => self.obp1 = data,
+      0xFF4A => self.wy = data,
+      0xFF4B => self.wx = data,
+      _ => {},
+    }
+  }
+
+  pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+    &self.framebuffer
+  }
+
+  // True once per frame, when the last visible line has been drawn.
+  pub fn take_frame(&mut self) -> bool {
+    let ready = self.frame_ready;
+    self.frame_ready = false;
+    ready
+  }
+
+  // Interrupts requested since the last call, as IF bits.
+  pub fn take_interrupts(&mut self) -> u8 {
+    let interrupts = self.interrupts;
+    self.interrupts = 0;
+    interrupts
+  }
+
+  // Advance by the given number of dots (T-cycles).
+  pub fn tick(&mut self, cycles: u32) {
+    if !self.lcd_enabled() {
+      return;
+    }
+    for _ in 0..cycles {
+      self.dot();
+    }
+  }
+
+  // Each visible line is OAM scan (80 dots), drawing (172 dots) and HBlank for the
+  // rest of the 456. Lines 144-153 are VBlank.
+  fn dot(&mut self) {
+    self.dots += 1;
+    if self.dots == OAM_SCAN_DOTS && self.mode == Mode::OamScan {
+      self.oam_scan();
+      self.mode = Mode::Drawing;
+      if self.renderer == Renderer::Fifo {
+        self.fifo_start_line();
+      }
+    } else if self.mode == Mode::Drawing {
+      let done = match self.renderer {
+        Renderer::Scanline => self.dots == OAM_SCAN_DOTS + DRAWING_DOTS,
+        Renderer::Fifo => self.fifo_dot(),
+      };
+      if done {
+        if self.renderer == Renderer::Scanline {
+          self.render_line();
+        }
+        self.mode = Mode::HBlank;
+      }
+    }
+
+    // LY already reads 0 a few dots into line 153.
+    if self.line == LINES_PER_FRAME - 1 && self.dots == 4 {
+      self.ly = 0;
+    }
+
+    if self.dots == DOTS_PER_LINE {
+      self.dots = 0;
+      self.line += 1;
+      if self.line as usize == SCREEN_HEIGHT {
+        self.mode = Mode::VBlank;
+        self.frame_ready = true;
+        self.interrupts |= Interrupt::VBlank as u8;
+      } else if self.line == LINES_PER_FRAME {
+        self.line = 0;
+        self.window_line = 0;
+        self.mode = Mode::OamScan;
+        self.fifo_start_frame();
+      } else if self.mode != Mode::VBlank {
+        self.mode = Mode::OamScan;
+      }
+      self.ly = self.line;
+    }
+
+    self.update_stat();
+  }
+
+  fn lcd_enabled(&self) -> bool {
+    self.lcdc & LCDC_LCD_ENABLE != 0
+  }
+
+  fn stat_register(&self) -> u8 {
+    let coincidence = if self.lcd_enabled() && self.ly == self.lyc { STAT_COINCIDENCE } else { 0 };
+    0x80 | self.stat | coincidence | self.mode as u8
+  }
+
+  // Recompute the STAT interrupt line and request the interrupt on a rising edge.
+  fn update_stat(&mut self) {
+    if !self.lcd_enabled() {
+      return;
+    }
+    let line = (self.stat & STAT_LYC_INT != 0 && self.ly == self.lyc)
+      || (self.stat & STAT_HBLANK_INT != 0 && self.mode == Mode::HBlank)
+      || (self.stat & STAT_VBLANK_INT != 0 && self.mode == Mode::VBlank)
+      || (self.stat & STAT_OAM_INT != 0 && self.mode == Mode::OamScan)
+      // The OAM source also fires when entering VBlank.
+      || (self.stat & STAT_OAM_INT != 0 && self.line as usize == SCREEN_HEIGHT && self.dots == 0);
+    if line && !self.stat_line {
+      self.interrupts |= Interrupt::Stat as u8;
+    }
+    self.stat_line = line;
+  }
+
+  fn render_line(&mut self) {
+    let y = self.ly;
+    let row = y as usize * SCREEN_WIDTH;
+    // Raw BG/window colour indexes, sprites need them to resolve priority.
+    let mut bg_colors = [0; SCREEN_WIDTH];
+
+    if self.lcdc & LCDC_BG_ENABLE == 0 {
+      // Background and window are blank (colour 0, ignoring BGP).
+      for x in 0..SCREEN_WIDTH {
+        self.framebuffer[row + x] = 0;
+      }
+    } else {
+      // Window is enabled and this line is at or below WY. WX is offset by 7.
+      let window_x = self.wx as i16 - 7;
+      let window_visible = self.lcdc & LCDC_WINDOW_ENABLE != 0 && y >= self.wy && self.wx <= 166;
+
+      for (x, bg_color) in bg_colors.iter_mut().enumerate() {
+        let color = if window_visible && x as i16 >= window_x {
+          let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x9C00 } else { 0x9800 };
+          self.bg_pixel(map, (x as i16 - window_x) as u8, self.window_line)
+        } else {
+          let map = if self.lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 };
+          self.bg_pixel(map, self.scx.wrapping_add(x as u8), self.scy.wrapping_add(y))
+        };
+        *bg_color = color;
+        self.framebuffer[row + x] = palette(self.bgp, color);
+      }
+
+      if window_visible {
+        self.window_line += 1;
+      }
+    }
+
+    if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+      self.render_sprites(&bg_colors);
+    }
+  }
+
+  // Pick the (up to 10) sprites overlapping the current line, in OAM order.
+  // Sprites off-screen horizontally still count towards the limit.
+  fn oam_scan(&mut self) {
+    self.line_sprites.clear();
+    let height = self.sprite_height();
+    for index in 0..40 {
+      let sprite = Sprite {
+        y: self.oam[index * 4],
+        x: self.oam[index * 4 + 1],
+        tile: self.oam[index * 4 + 2],
+        flags: self.oam[index * 4 + 3],
+      };
+      let top = sprite.y as i16 - 16;
+      let ly = self.ly as i16;
+      if ly >= top && ly < top + height as i16 {
+        self.line_sprites.push(sprite);
+        if self.line_sprites.len() == MAX_SPRITES_PER_LINE {
+          break;
+        }
+      }
+    }
+    // On DMG the sprite with the smallest X wins, ties go to the lowest OAM index.
+    // The sort is stable, so OAM order is kept for equal X.
+    self.line_sprites.sort_by_key(|sprite| sprite.x);
+  }
+
+  fn render_sprites(&mut self, bg_colors: &[u8; SCREEN_WIDTH]) {
+    let row = self.ly as usize * SCREEN_WIDTH;
+    let height = self.sprite_height();
+    for (x, bg_color) in bg_colors.iter().enumerate() {
+      for sprite in &self.line_sprites {
+        let sprite_x = x as i16 - (sprite.x as i16 - 8);
+        if !(0..8).contains(&sprite_x) {
+          continue;
+        }
+        let mut sprite_y = self.ly as i16 - (sprite.y as i16 - 16);
+        if sprite.flags & OBJ_Y_FLIP != 0 {
+          sprite_y = height as i16 - 1 - sprite_y;
+        }
+        let sprite_x = if sprite.flags & OBJ_X_FLIP != 0 { 7 - sprite_x } else { sprite_x };
+        // 8x16 sprites ignore bit 0 of the tile index; the bottom half is the next tile.
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let color = tile_pixel(&self.vram, tile as usize * 16, sprite_x as u8, sprite_y as u8);
+        // Transparent pixels let lower priority sprites through.
+        if color == 0 {
+          continue;
+        }
+        // The highest priority opaque sprite decides, even when it hides behind the BG.
+        if sprite.flags & OBJ_BG_PRIORITY == 0 || *bg_color == 0 {
+          let obp = if sprite.flags & OBJ_PALETTE != 0 { self.obp1 } else { self.obp0 };
+          self.framebuffer[row + x] = palette(obp, color);
+        }
+        break;
+      }
+    }
+  }
+
+  fn sprite_height(&self) -> u8 {
+    if self.lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 }
+  }
+
+  // Colour index (0-3) at pixel (x, y) of the 256x256 map starting at `map`.
+  fn bg_pixel(&self, map: u16, x: u8, y: u8) -> u8 {
+    let tile_index = self.vram[(map - 0x8000) as usize + (y as usize / 8) * 32 + x as usize / 8];
+    let tile = self.tile_address(tile_index);
+    tile_pixel(&self.vram, tile, x % 8, y % 8)
+  }
+
+  // VRAM offset of a BG/window tile. LCDC bit 4 selects between 0x8000 with
+  // unsigned indexes and 0x9000 with signed ones.
+  fn tile_address(&self, index: u8) -> usize {
+    if self.lcdc & LCDC_TILE_DATA != 0 {
+      index as usize * 16
+    } else {
+      (0x1000 + (index as i8 as i32) * 16) as usize
+    }
+  }
+}
+
+// Tiles are 8x8, 2 bits per pixel. Each row is two bytes: the low bits of
+// every pixel, then the high bits, leftmost pixel in bit 7.
+fn tile_pixel(vram: &[u8], tile: usize, x: u8, y: u8) -> u8 {
+  let lo = vram[tile + y as usize * 2];
+  let hi = vram[tile + y as usize * 2 + 1];
+  let bit = 7 - x;
+  (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+}
+
+// Map a colour index through a DMG palette register.
+fn palette(reg: u8, color: u8) -> u8 {
+  (reg >> (color * 2)) & 0b11
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const OBP0: u8 = 0xE4;
+  // Colour 1 is shade 3, 2 is 1 and 3 is 2.
+  const OBP1: u8 = 0x9C;
+
+  // Tile 1 has a single pixel of colour 3, top left. Tile 2 is all colour 1, tile 3 all
+  // colour 2, tiles 4 and 5 all colour 1 and 3 for 8x16 sprites.
+  fn load_tiles(ppu: &mut Ppu) {
+    ppu.write(0x8010, 0x80);
+    ppu.write(0x8011, 0x80);
+    for row in 0..8 {
+      ppu.write(0x8020 + row * 2, 0xFF);
+      ppu.write(0x8031 + row * 2, 0xFF);
+      ppu.write(0x8040 + row * 2, 0xFF);
+      ppu.write(0x8050 + row * 2, 0xFF);
+      ppu.write(0x8051 + row * 2, 0xFF);
+    }
+  }
+
+  // Sprite `index` with its top left corner at (x, y) on the screen.
+  fn sprite(ppu: &mut Ppu, index: u16, x: i16, y: i16, tile: u8, flags: u8) {
+    let addr = 0xFE00 + index * 4;
+    ppu.write(addr, (y + 16) as u8);
+    ppu.write(addr + 1, (x + 8) as u8);
+    ppu.write(addr + 2, tile);
+    ppu.write(addr + 3, flags);
+  }
+
+  // A frame with both renderers, which have to agree.
+  fn render<F: Fn(&mut Ppu)>(lcdc: u8, setup: F) -> Vec<u8> {
+    let frames: Vec<Vec<u8>> = [Renderer::Scanline, Renderer::Fifo].iter().map(|&renderer| {
+      let mut ppu = Ppu::new(Model::DMG);
+      ppu.set_renderer(renderer);
+      load_tiles(&mut ppu);
+      ppu.write(0xFF47, 0xE4);
+      ppu.write(0xFF48, OBP0);
+      ppu.write(0xFF49, OBP1);
+      setup(&mut ppu);
+      ppu.write(0xFF40, LCDC_LCD_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE | lcdc);
+      ppu.tick(DOTS_PER_LINE * LINES_PER_FRAME as u32);
+      ppu.framebuffer().to_vec()
+    }).collect();
+    assert!(frames[0] == frames[1], "the renderers disagree");
+    frames[0].clone()
+  }
+
+  fn pixel(frame: &[u8], x: usize, y: usize) -> u8 {
+    frame[y * SCREEN_WIDTH + x]
+  }
+
+  #[test]
+  fn flips_and_palettes() {
+    let frame = render(0, |ppu| {
+      sprite(ppu, 0, 10, 20, 1, 0);
+      sprite(ppu, 1, 30, 20, 1, OBJ_X_FLIP);
+      sprite(ppu, 2, 50, 20, 1, OBJ_Y_FLIP);
+      sprite(ppu, 3, 70, 20, 1, OBJ_PALETTE);
+    });
+    assert_eq!((pixel(&frame, 10, 20), pixel(&frame, 17, 20)), (3, 0));
+    assert_eq!((pixel(&frame, 30, 20), pixel(&frame, 37, 20)), (0, 3));
+    assert_eq!((pixel(&frame, 50, 20), pixel(&frame, 50, 27)), (0, 3));
+    assert_eq!(pixel(&frame, 70, 20), 2);
+  }
+
+  #[test]
+  fn priorities() {
+    let frame = render(0, |ppu| {
+      // Overlapping: the smaller X wins, whatever the OAM order.
+      sprite(ppu, 0, 20, 20, 2, 0);
+      sprite(ppu, 1, 16, 20, 2, OBJ_PALETTE);
+      // Same X: the lower OAM index wins.
+      sprite(ppu, 2, 40, 20, 2, 0);
+      sprite(ppu, 3, 40, 20, 2, OBJ_PALETTE);
+      // Transparent pixels show the sprite below.
+      sprite(ppu, 4, 60, 20, 1, OBJ_PALETTE);
+      sprite(ppu, 5, 61, 20, 2, 0);
+      // Behind BG colours 1-3, over colour 0. The top left BG tile is colour 2.
+      ppu.write(0x9800, 3);
+      sprite(ppu, 6, 4, 0, 2, OBJ_BG_PRIORITY);
+    });
+    assert_eq!((pixel(&frame, 17, 20), pixel(&frame, 22, 20), pixel(&frame, 26, 20)), (3, 3, 1));
+    assert_eq!(pixel(&frame, 40, 20), 1);
+    assert_eq!((pixel(&frame, 60, 20), pixel(&frame, 61, 20)), (2, 1));
+    assert_eq!((pixel(&frame, 5, 0), pixel(&frame, 9, 0)), (2, 1));
+  }
+
+  #[test]
+  fn ten_sprites_per_line() {
+    let frame = render(0, |ppu| {
+      // Off-screen sprites still count.
+      sprite(ppu, 0, -8, 20, 2, 0);
+      for i in 1..11 {
+        sprite(ppu, i, i as i16 * 10, 20, 2, 0);
+      }
+    });
+    assert_eq!(pixel(&frame, 90, 20), 1);
+    assert_eq!(pixel(&frame, 100, 20), 0);
+  }
+
+  #[test]
+  fn tall_sprites() {
+    // Bit 0 of the tile index is ignored: tile 4 on top, 5 below.
+    let frame = render(LCDC_OBJ_SIZE, |ppu| sprite(ppu, 0, 10, 20, 5, 0));
+    assert_eq!((pixel(&frame, 10, 20), pixel(&frame, 10, 30), pixel(&frame, 10, 36)), (1, 3, 0));
+  }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use super::*;
+
+// Dots before the first real fetch, so a line with no scroll, window or sprites
+// takes the 172 dots it does on hardware.
+const STARTUP_DOTS: u8 = 7;
+// A sprite fetch stalls the background fetcher for this long.
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+  color: u8,
+  obp1: bool,
+  bg_priority: bool,
+}
+
+// Pixel FIFO renderer. Instead of drawing a whole line at once, the background
+// fetcher and sprite fetcher feed pixels to the LCD one dot at a time, reading
+// registers as they go. Mid-line writes to SCX, BGP, LCDC... take effect on the
+// next pixel, and mode 3 gets longer with fine scroll, the window and sprites.
+pub struct Fifo {
+  bg: VecDeque<u8>,
+  sprites: VecDeque<SpritePixel>,
+
+  // Fetcher
+  fetch_dots: u8,
+  tile_x: u8,
+  tile_index: u8,
+  tile_lo: u8,
+  tile_hi: u8,
+  fetching_window: bool,
+
+  // Pixels pushed to the LCD on this line.
+  lx: u8,
+  // SCX fine scroll: pixels thrown away at the start of the line.
+  discard: u8,
+  stall: u8,
+  // Next sprite (in line_sprites) to be fetched.
+  next_sprite: usize,
+  sprite_fetch: u8,
+  // WY matched LY at some point this frame, the window can start.
+  window_y_triggered: bool,
+}
+
+impl Fifo {
+  pub fn new() -> Self {
+    Fifo {
+      bg: VecDeque::with_capacity(16),
+      sprites: VecDeque::with_capacity(8),
+      fetch_dots: 0,
+      tile_x: 0,
+      tile_index: 0,
+      tile_lo: 0,
+      tile_hi: 0,
+      fetching_window: false,
+      lx: 0,
+      discard: 0,
+      stall: 0,
+      next_sprite: 0,
+      sprite_fetch: 0,
+      window_y_triggered: false,
+    }
+  }
+}
+
+impl Ppu {
+  pub(super) fn fifo_start_frame(&mut self) {
+    self.fifo.window_y_triggered = false;
+  }
+
+  pub(super) fn fifo_start_line(&mut self) {
+    if self.ly == self.wy {
+      self.fifo.window_y_triggered = true;
+    }
+    let fifo = &mut self.fifo;
+    fifo.bg.clear();
+    fifo.sprites.clear();
+    fifo.fetch_dots = 0;
+    fifo.tile_x = 0;
+    fifo.fetching_window = false;
+    fifo.lx = 0;
+    fifo.discard = self.scx % 8;
+    fifo.stall = STARTUP_DOTS;
+    fifo.next_sprite = 0;
+    fifo.sprite_fetch = 0;
+  }
+
+  // Run one dot of mode 3. Returns true once all 160 pixels of the line are out.
+  pub(super) fn fifo_dot(&mut self) -> bool {
+    if self.fifo.stall > 0 {
+      self.fifo.stall -= 1;
+      return false;
+    }
+
+    self.check_window();
+
+    // A sprite fetch is in progress, the background waits.
+    if self.fifo.sprite_fetch > 0 {
+      self.fifo.sprite_fetch -= 1;
+      if self.fifo.sprite_fetch == 0 {
+        self.fetch_sprite();
+      }
+      return false;
+    }
+
+    self.fetcher_dot();
+
+    // A sprite starts at this pixel. The background fetcher has to have pixels
+    // ready before the sprite fetch can start.
+    if self.sprite_pending() {
+      if !self.fifo.bg.is_empty() {
+        self.fifo.sprite_fetch = SPRITE_FETCH_DOTS;
+      }
+      return false;
+    }
+
+    let color = match self.fifo.bg.pop_front() {
+      Some(color) => color,
+      None => return false,
+    };
+    if self.fifo.discard > 0 {
+      self.fifo.discard -= 1;
+      return false;
+    }
+
+    let bg_color = if self.lcdc & LCDC_BG_ENABLE != 0 { color } else { 0 };
+    let mut shade = if self.lcdc & LCDC_BG_ENABLE != 0 { palette(self.bgp, bg_color) } else { 0 };
+    if let Some(sprite) = self.fifo.sprites.pop_front() {
+      let visible = sprite.color != 0 && self.lcdc & LCDC_OBJ_ENABLE != 0;
+      if visible && (!sprite.bg_priority || bg_color == 0) {
+        shade = palette(if sprite.obp1 { self.obp1 } else { self.obp0 }, sprite.color);
+      }
+    }
+    let row = self.ly as usize * SCREEN_WIDTH;
+    self.framebuffer[row + self.fifo.lx as usize] = shade;
+    self.fifo.lx += 1;
+
+    if self.fifo.lx as usize == SCREEN_WIDTH {
+      if self.fifo.fetching_window {
+        self.window_line += 1;
+      }
+      return true;
+    }
+    false
+  }
+
+  // Start fetching window tiles once the LCD reaches WX - 7. The pixels already
+  // in the FIFO are dropped and the fetcher starts over.
+  fn check_window(&mut self) {
+    let fifo = &self.fifo;
+    if fifo.fetching_window || !fifo.window_y_triggered || self.lcdc & LCDC_WINDOW_ENABLE == 0 {
+      return;
+    }
+    if fifo.discard > 0 || fifo.lx as u16 + 7 < self.wx as u16 || self.wx > 166 {
+      return;
+    }
+    let fifo = &mut self.fifo;
+    fifo.fetching_window = true;
+    fifo.bg.clear();
+    fifo.fetch_dots = 0;
+    fifo.tile_x = 0;
+  }
+
+  // Background fetcher: tile index, low byte, high byte, two dots each, then
+  // push 8 pixels as soon as the FIFO is empty.
+  fn fetcher_dot(&mut self) {
+    self.fifo.fetch_dots += 1;
+    match self.fifo.fetch_dots {
+      2 => {
+        let address = if self.fifo.fetching_window {
+          let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+          map + (self.window_line as usize / 8) * 32 + self.fifo.tile_x as usize
+        } else {
+          let map = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+          let y = self.scy.wrapping_add(self.ly) as usize;
+          let x = (self.scx / 8).wrapping_add(self.fifo.tile_x) as usize % 32;
+          map + (y / 8) * 32 + x
+        };
+        self.fifo.tile_index = self.vram[address];
+      },
+      4 => self.fifo.tile_lo = self.vram[self.fetcher_row_address()],
+      6 => self.fifo.tile_hi = self.vram[self.fetcher_row_address() + 1],
+      _ => {},
+    }
+    if self.fifo.fetch_dots >= 6 && self.fifo.bg.is_empty() {
+      let (lo, hi) = (self.fifo.tile_lo, self.fifo.tile_hi);
+      for bit in (0..8).rev() {
+        self.fifo.bg.push_back((((hi >> bit) & 1) << 1) | ((lo >> bit) & 1));
+      }
+      self.fifo.fetch_dots = 0;
+      self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
+    }
+  }
+
+  fn fetcher_row_address(&self) -> usize {
+    let y = if self.fifo.fetching_window { self.window_line } else { self.scy.wrapping_add(self.ly) };
+    self.tile_address(self.fifo.tile_index) + (y as usize % 8) * 2
+  }
+
+  fn sprite_pending(&mut self) -> bool {
+    if self.fifo.discard > 0 || self.lcdc & LCDC_OBJ_ENABLE == 0 {
+      return false;
+    }
+    while let Some(sprite) = self.line_sprites.get(self.fifo.next_sprite) {
+      // X = 0 puts the sprite entirely off-screen, it's never fetched.
+      if sprite.x == 0 {
+        self.fifo.next_sprite += 1;
+        continue;
+      }
+      return sprite.x <= self.fifo.lx + 8;
+    }
+    false
+  }
+
+  // Merge the next sprite into the sprite FIFO. Pixels already there belong to
+  // higher priority sprites and are only replaced where they are transparent.
+  fn fetch_sprite(&mut self) {
+    let sprite = self.line_sprites[self.fifo.next_sprite];
+    self.fifo.next_sprite += 1;
+
+    let height = self.sprite_height();
+    let mut sprite_y = self.ly as i16 - (sprite.y as i16 - 16);
+    if sprite.flags & OBJ_Y_FLIP != 0 {
+      sprite_y = height as i16 - 1 - sprite_y;
+    }
+    let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+    // Sprites hanging off the left edge lose their leftmost pixels.
+    let skip = (self.fifo.lx + 8).saturating_sub(sprite.x);
+
+    for x in skip..8 {
+      let sprite_x = if sprite.flags & OBJ_X_FLIP != 0 { 7 - x } else { x };
+      let pixel = SpritePixel {
+        color: tile_pixel(&self.vram, tile as usize * 16, sprite_x, sprite_y as u8),
+        obp1: sprite.flags & OBJ_PALETTE != 0,
+        bg_priority: sprite.flags & OBJ_BG_PRIORITY != 0,
+      };
+      let position = (x - skip) as usize;
+      match self.fifo.sprites.get_mut(position) {
+        Some(existing) if existing.color == 0 => *existing = pixel,
+        Some(_) => {},
+        None => self.fifo.sprites.push_back(pixel),
+      }
+    }
+  }
+}
@@ -0,0 +1,349 @@
+use crate::link::Link;
+use crate::image::{numbered_path, save_png};
+
+const PAPER_WIDTH: usize = 160;
+// A data packet holds 2 rows of 20 tiles.
+const BAND_BYTES: usize = 640;
+// Status bits
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+// Status requests answered with "printing" after a print command, before it's done.
+const PRINT_POLLS: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+  Magic1,
+  Magic2,
+  Command,
+  Compression,
+  LengthLo,
+  LengthHi,
+  Data,
+  ChecksumLo,
+  ChecksumHi,
+  // The GameBoy sends two more bytes to get the printer's answer: 0x81 (it's
+  // there), then the status.
+  Alive,
+  Status,
+}
+
+// GameBoy Printer on the other end of the link cable. Games send it packets:
+//
+//   0x88 0x33 <command> <compression> <length, 2 bytes> <data> <checksum, 2 bytes> 0x00 0x00
+//
+// Commands are 0x01 (init), 0x02 (print what's buffered), 0x04 (640 bytes of tile
+// data, RLE compressed or not) and 0x0F (status). Every printout is saved as a PNG.
+pub struct Printer {
+  path: String,
+  printouts: u32,
+
+  state: State,
+  command: u8,
+  compressed: bool,
+  length: u16,
+  data: Vec<u8>,
+  checksum: u16,
+  received_checksum: u16,
+  status: u8,
+  print_polls: u8,
+  // Answer to the byte being sent.
+  reply: u8,
+
+  // 2bpp tile data waiting to be printed.
+  buffer: Vec<u8>,
+  // Printed rows (shades) not saved yet: printouts with no margin after them
+  // carry on in the next one.
+  paper: Vec<u8>,
+}
+
+impl Printer {
+  // Printouts are saved as out-1.png, out-2.png... for out.png.
+  pub fn new(path: &str) -> Self {
+    Printer {
+      path: path.to_string(),
+      printouts: 0,
+      state: State::Magic1,
+      command: 0,
+      compressed: false,
+      length: 0,
+      data: Vec::new(),
+      checksum: 0,
+      received_checksum: 0,
+      status: 0,
+      print_polls: 0,
+      reply: 0,
+      buffer: Vec::new(),
+      paper: Vec::new(),
+    }
+  }
+
+  // Take the next byte of a packet, returns the printer's answer to it.
+  fn receive_byte(&mut self, data: u8) -> u8 {
+    let mut reply = 0x00;
+    self.state = match self.state {
+      State::Magic1 if data == 0x88 => State::Magic2,
+      State::Magic1 => State::Magic1,
+      State::Magic2 if data == 0x33 => {
+        self.checksum = 0;
+        self.data.clear();
+        State::Command
+      },
+      // Out of sync, wait for the next packet.
+      State::Magic2 => State::Magic1,
+      State::Command => {
+        self.command = data;
+        self.checksum = data as u16;
+        State::Compression
+      },
+      State::Compression => {
+        self.compressed = data & 0x01 != 0;
+        self.checksum += data as u16;
+        State::LengthLo
+      },
+      State::LengthLo => {
+        self.length = data as u16;
+        self.checksum += data as u16;
+        State::LengthHi
+      },
+      State::LengthHi => {
+        self.length |= (data as u16) << 8;
+        self.checksum += data as u16;
+        if self.length > 0 { State::Data } else { State::ChecksumLo }
+      },
+      State::Data => {
+        self.data.push(data);
+        self.checksum = self.checksum.wrapping_add(data as u16);
+        if self.data.len() == self.length as usize { State::ChecksumLo } else { State::Data }
+      },
+      State::ChecksumLo => {
+        self.received_checksum = data as u16;
+        State::ChecksumHi
+      },
+      State::ChecksumHi => {
+        self.received_checksum |= (data as u16) << 8;
+        State::Alive
+      },
+      State::Alive => {
+        reply = 0x81;
+        self.run_command();
+        State::Status
+      },
+      State::Status => {
+        reply = self.status;
+        State::Magic1
+      },
+    };
+    reply
+  }
+
+  fn run_command(&mut self) {
+    if self.received_checksum != self.checksum {
+      self.status |= STATUS_CHECKSUM_ERROR;
+      return;
+    }
+    self.status &= !STATUS_CHECKSUM_ERROR;
+    match self.command {
+      0x01 => {
+        self.buffer.clear();
+        self.status = 0;
+        self.print_polls = 0;
+      },
+      0x02 if self.data.len() >= 4 => {
+        let margins = self.data[1];
+        let palette = self.data[2];
+        self.print(palette, margins & 0x0F);
+        self.status = (self.status & !STATUS_UNPROCESSED) | STATUS_PRINTING;
+        self.print_polls = PRINT_POLLS;
+      },
+      0x04 => {
+        let data = if self.compressed { decompress(&self.data) } else { self.data.clone() };
+        self.buffer.extend(data);
+        if !self.buffer.is_empty() {
+          self.status |= STATUS_UNPROCESSED;
+        }
+      },
+      0x0F if self.print_polls > 0 => {
+        self.print_polls -= 1;
+        if self.print_polls == 0 {
+          self.status &= !STATUS_PRINTING;
+        }
+      },
+      _ => {},
+    }
+  }
+
+  // Turn the buffered tiles into rows of paper. Bit pairs are mapped to shades by `palette`,
+  // like BGP (0 is taken as the usual 0xE4).
+  fn print(&mut self, palette: u8, margin_after: u8) {
+    let palette = if palette == 0 { 0xE4 } else { palette };
+    for band in self.buffer.chunks(BAND_BYTES) {
+      // 2 rows of 20 tiles
+      for tile_row in 0..band.len() / (20 * 16) {
+        for y in 0..8 {
+          for x in 0..PAPER_WIDTH {
+            let tile = &band[(tile_row * 20 + x / 8) * 16..];
+            let lo = (tile[y * 2] >> (7 - x % 8)) & 1;
+            let hi = (tile[y * 2 + 1] >> (7 - x % 8)) & 1;
+            let color = (hi << 1) | lo;
+            self.paper.push((palette >> (color * 2)) & 0x03);
+          }
+        }
+      }
+    }
+    self.buffer.clear();
+    if margin_after > 0 {
+      self.feed();
+    }
+  }
+
+  // Tear off the paper and save it.
+  fn feed(&mut self) {
+    if self.paper.is_empty() {
+      return;
+    }
+    self.printouts += 1;
+    let path = numbered_path(&self.path, self.printouts);
+    save_png(&path, PAPER_WIDTH, &self.paper);
+    println!("Printed {}", path);
+    self.paper.clear();
+  }
+}
+
+impl Drop for Printer {
+  fn drop(&mut self) {
+    self.feed();
+  }
+}
+
+// The GameBoy always drives the clock, the printer just answers each byte.
+impl Link for Printer {
+  fn send(&mut self, _cycle: u64, data: u8) {
+    self.reply = self.receive_byte(data);
+  }
+
+  fn receive(&mut self, _cycle: u64) -> u8 {
+    self.reply
+  }
+
+  fn incoming(&mut self, _cycle: u64) -> Option<u8> {
+    None
+  }
+
+  fn reply(&mut self, _data: u8) {}
+
+  fn sync(&mut self, _cycle: u64) {}
+}
+
+// Printer RLE: a control byte with bit 7 set repeats the next byte (control & 0x7F) + 2
+// times, otherwise (control + 1) bytes follow as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(BAND_BYTES);
+  let mut i = 0;
+  while i < data.len() {
+    let control = data[i];
+    i += 1;
+    if control & 0x80 != 0 {
+      let count = (control & 0x7F) as usize + 2;
+      if let Some(&byte) = data.get(i) {
+        out.extend(std::iter::repeat_n(byte, count));
+      }
+      i += 1;
+    } else {
+      let count = control as usize + 1;
+      let end = (i + count).min(data.len());
+      out.extend_from_slice(&data[i..end]);
+      i = end;
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn printer(name: &str) -> Printer {
+    let path = std::env::temp_dir().join(format!("gameboi-{}-{}.png", name, std::process::id()));
+    Printer::new(path.to_str().unwrap())
+  }
+
+  // Sends a whole packet, returns the two answers at the end of it.
+  fn packet_with_checksum(printer: &mut Printer, command: u8, compression: u8, data: &[u8], checksum: u16) -> (u8, u8) {
+    let mut bytes = vec![0x88, 0x33, command, compression, data.len() as u8, (data.len() >> 8) as u8];
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+    let replies: Vec<u8> = bytes.iter().map(|&byte| printer.receive_byte(byte)).collect();
+    assert!(replies[..replies.len() - 2].iter().all(|&reply| reply == 0));
+    (replies[replies.len() - 2], replies[replies.len() - 1])
+  }
+
+  fn packet(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+    let header = command as u16 + compression as u16 + (data.len() & 0xFF) as u16 + (data.len() >> 8) as u16;
+    let checksum = data.iter().fold(header, |sum, &byte| sum.wrapping_add(byte as u16));
+    packet_with_checksum(printer, command, compression, data, checksum)
+  }
+
+  // One band where every pixel has color 1.
+  fn band() -> Vec<u8> {
+    (0..BAND_BYTES).map(|i| if i % 2 == 0 { 0xFF } else { 0x00 }).collect()
+  }
+
+  #[test]
+  fn checksums() {
+    let mut printer = printer("checksums");
+    assert_eq!(packet(&mut printer, 0x01, 0, &[]), (0x81, 0));
+    assert_eq!(packet_with_checksum(&mut printer, 0x04, 0, &[1, 2, 3], 0x1234), (0x81, STATUS_CHECKSUM_ERROR));
+    // Nothing was buffered.
+    assert!(printer.buffer.is_empty());
+    assert_eq!(packet(&mut printer, 0x0F, 0, &[]), (0x81, 0));
+  }
+
+  #[test]
+  fn rle() {
+    assert_eq!(decompress(&[0x81, 0xAB, 0x02, 1, 2, 3, 0x80, 0xCD]), [0xAB, 0xAB, 0xAB, 1, 2, 3, 0xCD, 0xCD]);
+    // 640 bytes in 5 runs of 128.
+    let compressed = [0xFE, 0xFF].repeat(5);
+    assert_eq!(decompress(&compressed), vec![0xFF; BAND_BYTES]);
+
+    let mut printer = printer("rle");
+    assert_eq!(packet(&mut printer, 0x04, 1, &[0x81, 0xAB, 0x02, 1, 2, 3]), (0x81, STATUS_UNPROCESSED));
+    assert_eq!(printer.buffer, [0xAB, 0xAB, 0xAB, 1, 2, 3]);
+  }
+
+  #[test]
+  fn print_palette_and_margin() {
+    let mut printer = printer("palette");
+    packet(&mut printer, 0x01, 0, &[]);
+    packet(&mut printer, 0x04, 0, &band());
+    // No margin after: the paper stays in the printer. Color 1 is shade 3 with this palette.
+    assert_eq!(packet(&mut printer, 0x02, 0, &[0x01, 0x00, 0x0C, 0x40]), (0x81, STATUS_PRINTING));
+    assert!(printer.buffer.is_empty());
+    assert_eq!(printer.paper, vec![3; PAPER_WIDTH * 16]);
+    // Palette 0 is the usual 0xE4, and a margin tears the paper off.
+    packet(&mut printer, 0x04, 0, &band());
+    packet(&mut printer, 0x02, 0, &[0x01, 0x03, 0x00, 0x40]);
+    assert!(printer.paper.is_empty());
+    assert_eq!(printer.printouts, 1);
+    let path = numbered_path(&printer.path, 1);
+    let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+    let (info, _) = decoder.read_info().unwrap();
+    assert_eq!((info.width, info.height), (PAPER_WIDTH as u32, 32));
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn busy_while_printing() {
+    let mut printer = printer("busy");
+    packet(&mut printer, 0x01, 0, &[]);
+    packet(&mut printer, 0x04, 0, &band());
+    assert_eq!(packet(&mut printer, 0x02, 0, &[0x01, 0x00, 0xE4, 0x40]), (0x81, STATUS_PRINTING));
+    for _ in 1..PRINT_POLLS {
+      assert_eq!(packet(&mut printer, 0x0F, 0, &[]), (0x81, STATUS_PRINTING));
+    }
+    assert_eq!(packet(&mut printer, 0x0F, 0, &[]), (0x81, 0));
+    assert_eq!(packet(&mut printer, 0x0F, 0, &[]), (0x81, 0));
+    // The print still in the printer is thrown away.
+    printer.paper.clear();
+  }
+}
@@ -0,0 +1,147 @@
+use std::io::Write;
+use crate::bus::Interrupt;
+use crate::model::Model;
+use crate::link::Link;
+
+// T-cycles per bit with the internal clock: 8192 Hz, or 262144 Hz with the CGB's fast clock.
+const SLOW_BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+pub struct Serial {
+  sb: u8,
+  sc: u8,
+  cgb: bool,
+  // Bits left to shift in the current transfer, and T-cycles until the next one.
+  bits: u8,
+  bit_clock: u32,
+  interrupts: u8,
+  // T-cycles since power on, to timestamp transfers over the link.
+  cycle: u64,
+  // The other end of the cable, if one is plugged in.
+  link: Option<Box<dyn Link>>,
+  // Every byte sent goes here too, e.g. to see what test ROMs print.
+  capture: Option<Box<dyn Write>>,
+}
+
+impl Serial {
+  pub fn new(model: Model) -> Self {
+    Serial {
+      sb: 0,
+      sc: 0,
+      cgb: model.is_cgb(),
+      bits: 0,
+      bit_clock: 0,
+      interrupts: 0,
+      cycle: 0,
+      link: None,
+      capture: None,
+    }
+  }
+
+  pub fn connect(&mut self, link: Box<dyn Link>) {
+    self.link = Some(link);
+  }
+
+  pub fn set_capture(&mut self, capture: Box<dyn Write>) {
+    self.capture = Some(capture);
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match addr {
+      0xFF01 => self.sb,
+      // Only the CGB has the clock speed bit.
+      _ if self.cgb => self.sc | 0x7C,
+      _ => self.sc | 0x7E,
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0xFF01 => self.sb = data,
+      _ => {
+        self.sc = data;
+        if data & 0x80 != 0 {
+          self.start_transfer();
+        } else {
+          self.bits = 0;
+        }
+      },
+    }
+  }
+
+  fn start_transfer(&mut self) {
+    self.bits = 8;
+    self.bit_clock = self.bit_cycles();
+    if !self.internal_clock() {
+      return;
+    }
+    if let Some(link) = &mut self.link {
+      link.send(self.cycle, self.sb);
+    }
+    if let Some(capture) = &mut self.capture {
+      capture.write_all(&[self.sb]).unwrap();
+      capture.flush().unwrap();
+    }
+  }
+
+  fn internal_clock(&self) -> bool {
+    self.sc & 0x01 != 0
+  }
+
+  fn bit_cycles(&self) -> u32 {
+    if self.cgb && self.sc & 0x02 != 0 { FAST_BIT_CYCLES } else { SLOW_BIT_CYCLES }
+  }
+
+  pub fn take_interrupts(&mut self) -> u8 {
+    let interrupts = self.interrupts;
+    self.interrupts = 0;
+    interrupts
+  }
+
+  fn finish_transfer(&mut self) {
+    self.bits = 0;
+    self.sc &= 0x7F;
+    self.interrupts |= Interrupt::Serial as u8;
+  }
+
+  // With the external clock nothing happens until the other side clocks a transfer,
+  // and with nothing connected that's never.
+  pub fn tick(&mut self, cycles: u32) {
+    self.cycle += cycles as u64;
+    if let Some(mut link) = self.link.take() {
+      link.sync(self.cycle);
+      // The other side clocked a transfer: the bytes swap if we're waiting for one.
+      while let Some(data) = link.incoming(self.cycle) {
+        if self.bits > 0 && !self.internal_clock() {
+          link.reply(self.sb);
+          self.sb = data;
+          self.finish_transfer();
+        } else {
+          link.reply(0xFF);
+        }
+      }
+      self.link = Some(link);
+    }
+
+    if self.bits == 0 || !self.internal_clock() {
+      return;
+    }
+    let mut cycles = cycles;
+    while self.bits > 0 && cycles >= self.bit_clock {
+      cycles -= self.bit_clock;
+      self.bit_clock = self.bit_cycles();
+      // Nothing connected: the line stays high and 1s shift in.
+      self.sb = (self.sb << 1) | 1;
+      self.bits -= 1;
+      if self.bits == 0 {
+        if let Some(link) = &mut self.link {
+          self.sb = link.receive(self.cycle);
+        }
+        self.finish_transfer();
+      }
+    }
+    if self.bits > 0 {
+      self.bit_clock -= cycles;
+    }
+  }
+}
@@ -0,0 +1,132 @@
+use crate::bus::Interrupt;
+
+pub struct Timer {
+  // DIV is the upper 8 bits of this counter, which goes up every T-cycle.
+  counter: u16,
+  tima: u8,
+  tma: u8,
+  tac: u8,
+
+  // TIMA overflowed on the last cycle and reads 0. It gets TMA (and the interrupt
+  // fires) one machine cycle later, unless TIMA is written in between.
+  overflow: bool,
+  // TMA is being copied into TIMA this cycle. Writes to TIMA are ignored and
+  // writes to TMA go straight through to TIMA.
+  reloading: bool,
+  interrupts: u8,
+  // Falling edges of DIV bit 4, which clock the APU frame sequencer.
+  apu_clocks: u8,
+}
+
+impl Timer {
+  pub fn new(counter: u16) -> Self {
+    Timer {
+      counter,
+      tima: 0,
+      tma: 0,
+      tac: 0,
+      overflow: false,
+      reloading: false,
+      interrupts: 0,
+      apu_clocks: 0,
+    }
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match addr {
+      0xFF04 => (self.counter >> 8) as u8,
+      0xFF05 => self.tima,
+      0xFF06 => self.tma,
+      0xFF07 => self.tac | 0xF8,
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, data: u8) {
+    // Resetting DIV or changing TAC can take the selected bit from 1 to 0, which
+    // the edge detector sees as a tick.
+    let signal = self.signal();
+    match addr {
+      0xFF04 => {
+        self.count_apu_clock(self.counter, 0);
+        self.counter = 0;
+      },
+      0xFF05 if !self.reloading => {
+        self.tima = data;
+        self.overflow = false;
+      },
+      0xFF06 => {
+        self.tma = data;
+        if self.reloading {
+          self.tima = data;
+        }
+      },
+      0xFF07 => self.tac = data & 0x07,
+      _ => {},
+    }
+    self.detect_edge(signal);
+  }
+
+  // Interrupts requested since the last call, as IF bits.
+  pub fn take_interrupts(&mut self) -> u8 {
+    let interrupts = self.interrupts;
+    self.interrupts = 0;
+    interrupts
+  }
+
+  // APU frame sequencer clocks since the last call.
+  pub fn take_apu_clocks(&mut self) -> u8 {
+    let clocks = self.apu_clocks;
+    self.apu_clocks = 0;
+    clocks
+  }
+
+  pub fn tick(&mut self, cycles: u32) {
+    for _ in 0..cycles / 4 {
+      self.step();
+    }
+  }
+
+  // One machine cycle.
+  fn step(&mut self) {
+    self.reloading = false;
+    if self.overflow {
+      self.overflow = false;
+      self.tima = self.tma;
+      self.reloading = true;
+      self.interrupts |= Interrupt::Timer as u8;
+    }
+    let signal = self.signal();
+    let counter = self.counter.wrapping_add(4);
+    self.count_apu_clock(self.counter, counter);
+    self.counter = counter;
+    self.detect_edge(signal);
+  }
+
+  // DIV bit 4 is counter bit 12.
+  fn count_apu_clock(&mut self, before: u16, after: u16) {
+    if before & 0x1000 != 0 && after & 0x1000 == 0 {
+      self.apu_clocks += 1;
+    }
+  }
+
+  // The counter bit TAC selects, ANDed with the enable bit.
+  fn signal(&self) -> bool {
+    let bit = match self.tac & 0x03 {
+      0 => 9, // 4096 Hz
+      1 => 3, // 262144 Hz
+      2 => 5, // 65536 Hz
+      _ => 7, // 16384 Hz
+    };
+    self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+  }
+
+  // TIMA goes up on the falling edge of the signal.
+  fn detect_edge(&mut self, before: bool) {
+    if before && !self.signal() {
+      let (tima, overflow) = self.tima.overflowing_add(1);
+      self.tima = tima;
+      self.overflow = overflow;
+    }
+  }
+}
@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+// VGM timestamps are in samples at 44100 Hz.
+const VGM_RATE: u64 = 44100;
+const CLOCK_RATE: u64 = 4194304;
+const HEADER_SIZE: u32 = 0x100;
+
+// Logs writes to the sound registers as a VGM file (v1.61, GameBoy DMG chip), so
+// music can be played back in any VGM player.
+pub struct VgmLog {
+  file: BufWriter<File>,
+  // Samples written out as waits so far.
+  samples: u64,
+}
+
+impl VgmLog {
+  pub fn create(path: &str) -> Self {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    let mut header = [0; HEADER_SIZE as usize];
+    header[0x00..0x04].copy_from_slice(b"Vgm ");
+    header[0x08..0x0C].copy_from_slice(&0x161u32.to_le_bytes());
+    // Data starts right after the header, relative to this field.
+    header[0x34..0x38].copy_from_slice(&(HEADER_SIZE - 0x34).to_le_bytes());
+    header[0x80..0x84].copy_from_slice(&(CLOCK_RATE as u32).to_le_bytes());
+    file.write_all(&header).unwrap();
+    VgmLog { file, samples: 0 }
+  }
+
+  // `data` was written to `addr` (0xFF10-0xFF3F), `cycles` T-cycles after the log started.
+  pub fn write(&mut self, cycles: u64, addr: u16, data: u8) {
+    self.wait_until(cycles * VGM_RATE / CLOCK_RATE);
+    self.file.write_all(&[0xB3, (addr - 0xFF10) as u8, data]).unwrap();
+  }
+
+  fn wait_until(&mut self, samples: u64) {
+    while self.samples < samples {
+      let wait = (samples - self.samples).min(0xFFFF);
+      if wait <= 16 {
+        self.file.write_all(&[0x70 + (wait - 1) as u8]).unwrap();
+      } else {
+        self.file.write_all(&[0x61]).unwrap();
+        self.file.write_all(&(wait as u16).to_le_bytes()).unwrap();
+      }
+      self.samples += wait;
+    }
+  }
+
+  // Pad out to `cycles`, end the data and fill in the sizes in the header.
+  pub fn finish(&mut self, cycles: u64) {
+    self.wait_until(cycles * VGM_RATE / CLOCK_RATE);
+    self.file.write_all(&[0x66]).unwrap();
+    let size = self.file.stream_position().unwrap() as u32;
+    self.file.seek(SeekFrom::Start(0x04)).unwrap();
+    self.file.write_all(&(size - 4).to_le_bytes()).unwrap();
+    self.file.seek(SeekFrom::Start(0x18)).unwrap();
+    self.file.write_all(&(self.samples as u32).to_le_bytes()).unwrap();
+    self.file.flush().unwrap();
+  }
+}
@@ -0,0 +1,8 @@
+// A 32KB cartridge running `code` from 0x0150.
+pub fn rom(code: &[u8]) -> Vec<u8> {
+  let mut rom = vec![0; 0x8000];
+  // nop, jp 0x0150
+  rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+  rom[0x150..0x150 + code.len()].copy_from_slice(code);
+  rom
+}
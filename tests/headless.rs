@@ -0,0 +1,52 @@
+mod common;
+
+use std::fs::{self, File};
+use gameboi::{Gameboy, headless};
+use gameboi::bus::Bus;
+use gameboi::model::Model;
+use gameboi::audio::NullAudio;
+
+#[test]
+fn input_script_reaches_the_joypad() {
+  // ld a,0x10; ldh (P1),a: read the action buttons.
+  // Then forever: ldh a,(P1); ld (0xC000),a
+  let game = common::rom(&[0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0xEA, 0x00, 0xC0, 0xC3, 0x54, 0x01]);
+  let script = std::env::temp_dir().join(format!("gameboi-input-{}.txt", std::process::id()));
+  fs::write(&script, "# Start goes back up, A stays down\n2 press start\n3 press a\n4 release start\n").unwrap();
+  let options = headless::Options {
+    frames: 6,
+    input: Some(script.to_string_lossy().into_owned()),
+    screenshot: None,
+    screenshot_every: None,
+  };
+  let mut bus = Bus::new(Model::DMG);
+  let mut gb = Gameboy::new(Model::DMG, game);
+  gb.connect_bus(&mut bus);
+  headless::run(&mut gb, &options, &mut NullAudio::new(48000));
+  fs::remove_file(&script).unwrap();
+  // Pressed buttons read as 0: A is bit 0, Start bit 3.
+  assert_eq!(gb.cpu.read(0xC000) & 0x0F, 0x0E);
+}
+
+#[test]
+fn linked_screenshot_without_frames() {
+  let screenshot = std::env::temp_dir().join(format!("gameboi-linked-{}.png", std::process::id()));
+  let options = headless::Options {
+    frames: 0,
+    input: None,
+    screenshot: Some(screenshot.to_string_lossy().into_owned()),
+    screenshot_every: None,
+  };
+  let mut buses = [Bus::new(Model::DMG), Bus::new(Model::DMG)];
+  let mut gbs: Vec<Gameboy> = buses.iter_mut().map(|bus| {
+    // jp 0x0150
+    let mut gb = Gameboy::new(Model::DMG, common::rom(&[0xC3, 0x50, 0x01]));
+    gb.connect_bus(bus);
+    gb
+  }).collect();
+  headless::run_linked(&mut gbs, &options, &mut NullAudio::new(48000));
+  let (info, _) = png::Decoder::new(File::open(&screenshot).unwrap()).read_info().unwrap();
+  fs::remove_file(&screenshot).unwrap();
+  // Both screens side by side.
+  assert_eq!((info.width, info.height), (320, 144));
+}
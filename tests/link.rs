@@ -0,0 +1,35 @@
+mod common;
+
+use gameboi::{Gameboy, lockstep};
+use gameboi::bus::Bus;
+use gameboi::model::Model;
+use gameboi::link::LocalLink;
+
+#[test]
+fn local_link_swaps_a_byte() {
+  // nop; nop; ld a,0x48; ldh (SB),a; ld a,0x81; ldh (SC),a; jp 0x015A
+  // The nops give the slave time to get ready.
+  let master = common::rom(&[0x00, 0x00, 0x3E, 0x48, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xC3, 0x5A, 0x01]);
+  // ld a,0x58; ldh (SB),a; ld a,0x80; ldh (SC),a; jp 0x0158: waits for the master
+  // with the external clock.
+  let slave = common::rom(&[0x3E, 0x58, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0xC3, 0x58, 0x01]);
+
+  let mut buses = [Bus::new(Model::DMG), Bus::new(Model::DMG)];
+  let mut gbs: Vec<Gameboy> = buses.iter_mut().zip(vec![master, slave]).map(|(bus, game)| {
+    let mut gb = Gameboy::new(Model::DMG, game);
+    gb.connect_bus(bus);
+    gb
+  }).collect();
+  let (link, link2) = LocalLink::pair();
+  gbs[0].cpu.bus_mut().serial.connect(Box::new(link));
+  gbs[1].cpu.bus_mut().serial.connect(Box::new(link2));
+
+  for _ in 0..10 {
+    lockstep::run_frame(&mut gbs);
+  }
+  assert_eq!(gbs[0].cpu.bus().read(0xFF01), 0x58);
+  assert_eq!(gbs[1].cpu.bus().read(0xFF01), 0x48);
+  // Both transfers are done.
+  assert_eq!(gbs[0].cpu.bus().read(0xFF02) & 0x80, 0);
+  assert_eq!(gbs[1].cpu.bus().read(0xFF02) & 0x80, 0);
+}
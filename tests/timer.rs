@@ -0,0 +1,28 @@
+mod common;
+
+use gameboi::Gameboy;
+use gameboi::bus::Bus;
+use gameboi::model::Model;
+
+// TIMA as the CPU sees it depends on the cycle of each access within an instruction.
+#[test]
+fn tima_reads_happen_at_their_machine_cycle() {
+  let game = common::rom(&[
+    0x3E, 0x00, 0xE0, 0x04, // ld a,0; ldh (DIV),a: the counter starts over at 0.
+    0x3E, 0x05, 0xE0, 0x07, // ld a,5; ldh (TAC),a: TIMA goes up every 16 T-cycles.
+    0xF0, 0x05, 0xE0, 0x80, // ldh a,(TIMA); ldh (0xFF80),a
+    0xF0, 0x05, 0xE0, 0x81, // ldh a,(TIMA); ldh (0xFF81),a
+    0xC3, 0x60, 0x01,       // jp 0x0160
+  ]);
+  let mut bus = Bus::new(Model::DMG);
+  let mut gb = Gameboy::new(Model::DMG, game);
+  gb.connect_bus(&mut bus);
+  while gb.cpu.pc() != 0x0160 {
+    gb.step();
+  }
+  // DIV is written on its instruction's 3rd machine cycle, the counter is 4 after it.
+  // TAC gets written at 20 and TIMA read at 32, after the falling edge of bit 3 at 32.
+  // The second read is at 56, after the one at 48 too.
+  assert_eq!(gb.cpu.read(0xFF80), 1);
+  assert_eq!(gb.cpu.read(0xFF81), 2);
+}